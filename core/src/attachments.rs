@@ -0,0 +1,241 @@
+use near_sdk::{
+    env,
+    json_types::{Base58CryptoHash, U64},
+    near, AccountId, FunctionError,
+};
+
+use crate::{
+    content::MAX_LOCATOR_LENGTH,
+    errors::{ensure, ContractError},
+    pausable::{Feature, Pausable},
+    Contract, ContractExt,
+};
+
+// Attachments a single note can have
+pub(crate) const MAX_ATTACHMENTS: usize = 10;
+pub(crate) const MAX_ATTACHMENT_NAME_LENGTH: usize = 128;
+// RFC 6838 bounds both the type and the subtype to 127 characters
+pub(crate) const MAX_MIME_TYPE_LENGTH: usize = 255;
+
+// A file kept off chain and linked from a note, such as a screenshot or a
+// PDF. Its name is unique within the note
+#[near(serializers = [borsh, json])]
+#[derive(Clone, PartialEq, Eq)]
+pub struct Attachment {
+    pub name: String,
+    pub mime_type: String,
+    // Size of the file in bytes
+    pub size: U64,
+    pub sha256: Base58CryptoHash,
+    pub uri: String,
+}
+
+impl Attachment {
+    fn assert_valid(&self) {
+        let mime_type = self.mime_type.split_once('/');
+
+        ensure!(
+            !self.name.is_empty()
+                && self.name.len() <= MAX_ATTACHMENT_NAME_LENGTH
+                && self.mime_type.len() <= MAX_MIME_TYPE_LENGTH
+                && mime_type.is_some_and(|(kind, subtype)| {
+                    !kind.is_empty() && !subtype.is_empty()
+                })
+                && !self.uri.is_empty()
+                && self.uri.len() <= MAX_LOCATOR_LENGTH,
+            ContractError::InvalidAttachment
+        );
+    }
+}
+
+#[near]
+impl Contract {
+    // The author attaches a file to one of their notes. The attachment is
+    // paid for from their storage balance, topped up by the attached deposit
+    #[payable]
+    pub fn add_attachment(&mut self, id: U64, attachment: Attachment) {
+        self.assert_feature_not_paused(Feature::Notes);
+        self.assert_not_migrating();
+
+        attachment.assert_valid();
+
+        let account_id = env::predecessor_account_id();
+
+        ensure!(
+            self.notes.contains_key(&(account_id.clone(), id.0)),
+            ContractError::NoteNotFound
+        );
+
+        let deposit = env::attached_deposit().as_yoctonear();
+
+        if deposit != 0 {
+            self.internal_storage_deposit(&account_id, deposit);
+        }
+
+        let storage_usage = env::storage_usage();
+
+        let note = self
+            .notes
+            .get_mut(&(account_id.clone(), id.0))
+            .unwrap_or_else(|| ContractError::NoteNotFound.panic());
+
+        ensure!(
+            note.attachments.len() < MAX_ATTACHMENTS,
+            ContractError::TooManyAttachments
+        );
+        ensure!(
+            !note
+                .attachments
+                .iter()
+                .any(|existing| existing.name == attachment.name),
+            ContractError::DuplicateAttachment
+        );
+
+        note.attachments.push(attachment);
+
+        self.notes.flush();
+
+        self.internal_settle_storage(&account_id, storage_usage);
+    }
+
+    // The freed storage is credited back to the storage balance of the author
+    pub fn remove_attachment(&mut self, id: U64, name: String) {
+        self.assert_feature_not_paused(Feature::Notes);
+        self.assert_not_migrating();
+
+        let account_id = env::predecessor_account_id();
+
+        let storage_usage = env::storage_usage();
+
+        let note = self
+            .notes
+            .get_mut(&(account_id.clone(), id.0))
+            .unwrap_or_else(|| ContractError::NoteNotFound.panic());
+
+        let index = note
+            .attachments
+            .iter()
+            .position(|attachment| attachment.name == name)
+            .unwrap_or_else(|| ContractError::AttachmentNotFound.panic());
+
+        note.attachments.remove(index);
+
+        self.notes.flush();
+
+        self.internal_settle_storage(&account_id, storage_usage);
+    }
+
+    // Attachments of a note, without loading its body into the response
+    pub fn get_attachments(
+        &self,
+        account_id: AccountId,
+        id: U64,
+    ) -> &Vec<Attachment> {
+        &self
+            .notes
+            .get(&(account_id, id.0))
+            .unwrap_or_else(|| ContractError::NoteNotFound.panic())
+            .attachments
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use near_contract_standards::storage_management::StorageManagement;
+    use near_sdk::NearToken;
+
+    use super::*;
+    use crate::test_utils::{new_contract, set_context};
+
+    #[test]
+    fn add_attachment() {
+        let mut contract = new_contract();
+
+        set_context("account_id1", NearToken::from_near(1));
+        contract.add_note("title".into(), "body".into(), None);
+
+        let account_id: AccountId = "account_id1".parse().unwrap();
+
+        let available = contract
+            .storage_balance_of(account_id.clone())
+            .unwrap()
+            .available;
+
+        set_context("account_id1", NearToken::from_near(0));
+        contract.add_attachment(U64(0), attachment("screenshot.png"));
+        contract.add_attachment(U64(0), attachment("report.pdf"));
+
+        let attachments = contract.get_attachments(account_id.clone(), U64(0));
+        assert_eq!(attachments.len(), 2);
+        assert_eq!(attachments[1].name, "report.pdf");
+
+        // attachments are charged to the storage balance of the author
+        let charged = contract
+            .storage_balance_of(account_id.clone())
+            .unwrap()
+            .available;
+        assert!(charged < available);
+
+        contract.remove_attachment(U64(0), "screenshot.png".into());
+        contract.remove_attachment(U64(0), "report.pdf".into());
+
+        assert!(contract
+            .get_attachments(account_id.clone(), U64(0))
+            .is_empty());
+        assert_eq!(
+            contract.storage_balance_of(account_id).unwrap().available,
+            available
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_DUPLICATE_ATTACHMENT")]
+    fn add_duplicate_attachment() {
+        let mut contract = new_contract();
+
+        set_context("account_id1", NearToken::from_near(1));
+        contract.add_note("title".into(), "body".into(), None);
+
+        contract.add_attachment(U64(0), attachment("screenshot.png"));
+        contract.add_attachment(U64(0), attachment("screenshot.png"));
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_INVALID_ATTACHMENT")]
+    fn add_attachment_without_mime_type() {
+        let mut contract = new_contract();
+
+        set_context("account_id1", NearToken::from_near(1));
+        contract.add_note("title".into(), "body".into(), None);
+
+        contract.add_attachment(
+            U64(0),
+            Attachment {
+                mime_type: "png".into(),
+                ..attachment("screenshot.png")
+            },
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_NOTE_NOT_FOUND")]
+    fn add_attachment_to_note_of_other_account() {
+        let mut contract = new_contract();
+
+        set_context("account_id1", NearToken::from_near(1));
+        contract.add_note("title".into(), "body".into(), None);
+
+        set_context("account_id2", NearToken::from_near(1));
+        contract.add_attachment(U64(0), attachment("screenshot.png"));
+    }
+
+    fn attachment(name: &str) -> Attachment {
+        Attachment {
+            name: name.into(),
+            mime_type: "image/png".into(),
+            size: U64(1024),
+            sha256: env::sha256_array(name.as_bytes()).into(),
+            uri: format!("ipfs://bafybeigdyrzt5sfp7udm7hu76uh7y26nf3/{name}"),
+        }
+    }
+}
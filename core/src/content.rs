@@ -0,0 +1,235 @@
+use near_sdk::{
+    env,
+    json_types::{Base58CryptoHash, U64},
+    near, AccountId,
+};
+
+use crate::{
+    encryption::EncryptedBody,
+    errors::{ensure, ContractError},
+    pausable::{Feature, Pausable},
+    tags::assert_valid_tags,
+    Contract, ContractExt, PostedNote,
+};
+
+// Longest CID or URL a content reference can hold
+pub(crate) const MAX_LOCATOR_LENGTH: usize = 256;
+
+// Where the content of a note stored off chain can be fetched from, on IPFS,
+// Arweave or any other host, and the sha256 it has to match once fetched
+#[near(serializers = [borsh, json])]
+#[derive(Clone, PartialEq, Eq)]
+pub struct ContentRef {
+    pub cid: Option<String>,
+    pub url: Option<String>,
+    pub sha256: Base58CryptoHash,
+}
+
+impl ContentRef {
+    pub(crate) fn assert_valid(&self) {
+        let valid = |locator: &String| {
+            !locator.is_empty() && locator.len() <= MAX_LOCATOR_LENGTH
+        };
+
+        ensure!(
+            (self.cid.is_some() || self.url.is_some())
+                && self.cid.iter().chain(self.url.iter()).all(valid),
+            ContractError::InvalidContentRef
+        );
+    }
+}
+
+// The content of a note, which is either an inline `body`, an encrypted body
+// or a reference to content stored off chain
+pub(crate) struct NoteContent {
+    pub body: String,
+    pub encrypted: Option<EncryptedBody>,
+    pub content_ref: Option<ContentRef>,
+}
+
+impl NoteContent {
+    pub fn plain(body: String) -> Self {
+        Self {
+            body,
+            encrypted: None,
+            content_ref: None,
+        }
+    }
+
+    pub fn encrypted(encrypted: EncryptedBody) -> Self {
+        Self {
+            body: String::new(),
+            encrypted: Some(encrypted),
+            content_ref: None,
+        }
+    }
+
+    pub fn reference(content_ref: ContentRef) -> Self {
+        Self {
+            body: String::new(),
+            encrypted: None,
+            content_ref: Some(content_ref),
+        }
+    }
+}
+
+#[near]
+impl Contract {
+    // Like `add_note`, but only a reference to the content is stored, which
+    // keeps large notes cheap. The note is otherwise a regular one
+    #[payable]
+    pub fn add_note_ref(
+        &mut self,
+        title: String,
+        content_ref: ContentRef,
+        tags: Option<Vec<String>>,
+    ) -> U64 {
+        self.assert_feature_not_paused(Feature::Notes);
+
+        content_ref.assert_valid();
+
+        let account_id = env::predecessor_account_id();
+
+        let mut note = PostedNote::new(
+            title,
+            String::new(),
+            None,
+            account_id.clone(),
+            tags.unwrap_or_default(),
+        );
+        note.content_ref = Some(content_ref);
+
+        let id = self.internal_add_note(
+            account_id,
+            note,
+            env::attached_deposit().as_yoctonear(),
+        );
+
+        U64(id)
+    }
+
+    // Like `update_note`, for notes added with `add_note_ref`
+    #[payable]
+    pub fn update_note_ref(
+        &mut self,
+        id: U64,
+        title: String,
+        content_ref: ContentRef,
+        tags: Option<Vec<String>>,
+        author: Option<AccountId>,
+    ) {
+        self.assert_feature_not_paused(Feature::Notes);
+        self.assert_not_migrating();
+
+        content_ref.assert_valid();
+
+        if let Some(tags) = &tags {
+            assert_valid_tags(tags);
+        }
+
+        let account_id = author.unwrap_or_else(env::predecessor_account_id);
+
+        self.assert_can_write(&account_id, id.0);
+
+        let deposit = env::attached_deposit().as_yoctonear();
+
+        if deposit != 0 {
+            self.internal_storage_deposit(&account_id, deposit);
+        }
+
+        self.internal_update_note(
+            account_id,
+            id,
+            title,
+            NoteContent::reference(content_ref),
+            tags,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use near_sdk::NearToken;
+
+    use super::*;
+    use crate::{
+        crypto,
+        test_utils::{new_contract, set_context},
+    };
+
+    #[test]
+    fn add_note_ref() {
+        let mut contract = new_contract();
+
+        let content = b"a long document".to_vec();
+
+        set_context("account_id1", NearToken::from_near(1));
+        let id = contract.add_note_ref(
+            "title".into(),
+            content_ref_of(&content),
+            None,
+        );
+
+        let note = contract.get_note("account_id1".parse().unwrap(), id);
+        assert!(note.body.is_empty());
+
+        let content_ref = note.content_ref.as_ref().unwrap();
+        assert!(crypto::verify_content(&content, content_ref));
+        assert!(!crypto::verify_content(b"tampered", content_ref));
+
+        contract.update_note_ref(
+            id,
+            "title".into(),
+            content_ref_of(b"a longer document"),
+            None,
+            None,
+        );
+
+        let history =
+            contract.get_note_history("account_id1".parse().unwrap(), id);
+        assert!(
+            history[0].content_ref.as_ref().unwrap().sha256
+                == Base58CryptoHash::from(env::sha256_array(&content))
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_INVALID_CONTENT_REF")]
+    fn add_note_ref_without_locator() {
+        let mut contract = new_contract();
+
+        set_context("account_id1", NearToken::from_near(1));
+        contract.add_note_ref(
+            "title".into(),
+            ContentRef {
+                cid: None,
+                url: None,
+                sha256: env::sha256_array(b"content").into(),
+            },
+            None,
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_CONTENT_REF_MISMATCH")]
+    fn update_note_ref_in_plain() {
+        let mut contract = new_contract();
+
+        set_context("account_id1", NearToken::from_near(1));
+        let id =
+            contract.add_note_ref("title".into(), content_ref_of(b"doc"), None);
+
+        contract.update_note(id, "title".into(), "body".into(), None, None);
+    }
+
+    fn content_ref_of(content: &[u8]) -> ContentRef {
+        ContentRef {
+            cid: Some(
+                "bafybeigdyrzt5sfp7udm7hu76uh7y26nf3efuylqabf3oclgtqy55fbzdi"
+                    .into(),
+            ),
+            url: None,
+            sha256: env::sha256_array(content).into(),
+        }
+    }
+}
@@ -0,0 +1,182 @@
+// Off-chain helpers creating and opening the envelopes of encrypted notes and
+// verifying the content of notes stored off chain, available with the `crypto`
+// feature. They aren't part of the contract
+use chacha20poly1305::{
+    aead::{rand_core::RngCore, Aead, AeadCore, KeyInit, OsRng, Payload},
+    ChaCha20Poly1305, Key, Nonce,
+};
+use near_sdk::{AccountId, CryptoHash};
+use sha2::{Digest, Sha256};
+use x25519_dalek::{EphemeralSecret, PublicKey, StaticSecret};
+
+use crate::{
+    content::ContentRef,
+    encryption::{
+        EncryptedBody, EncryptionScheme, KeyEnvelope, CONTEXT_LENGTH,
+        KEY_LENGTH, NONCE_LENGTH,
+    },
+};
+
+pub type ContentKey = [u8; KEY_LENGTH];
+
+// The key pair an account registers with `set_encryption_key`
+pub fn generate_keypair() -> (StaticSecret, PublicKey) {
+    let secret = StaticSecret::random_from_rng(OsRng);
+    let public_key = PublicKey::from(&secret);
+
+    (secret, public_key)
+}
+
+// Encrypts the body of a new note of `author` with a fresh content key, which
+// still has to be sealed for every recipient, and a fresh context
+pub fn encrypt_body(
+    body: &str,
+    author: &AccountId,
+) -> (EncryptedBody, ContentKey) {
+    let key: ContentKey = ChaCha20Poly1305::generate_key(&mut OsRng).into();
+
+    let mut context = [0; CONTEXT_LENGTH];
+    OsRng.fill_bytes(&mut context);
+
+    (encrypt_body_with_key(&key, body, author, &context), key)
+}
+
+// Encrypts a new body of an existing note under its content key and context,
+// which the existing envelopes keep opening
+pub fn encrypt_body_with_key(
+    key: &ContentKey,
+    body: &str,
+    author: &AccountId,
+    context: &[u8],
+) -> EncryptedBody {
+    let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+
+    let ciphertext = ChaCha20Poly1305::new(Key::from_slice(key))
+        .encrypt(
+            &nonce,
+            Payload {
+                msg: body.as_bytes(),
+                aad: &associated_data(author, context),
+            },
+        )
+        .expect("encryption failure");
+
+    EncryptedBody {
+        scheme: EncryptionScheme::X25519ChaCha20Poly1305,
+        context: context.to_vec().into(),
+        nonce: nonce.to_vec().into(),
+        ciphertext: ciphertext.into(),
+    }
+}
+
+// `None` unless the body was encrypted under `key` for a note of `author`
+pub fn decrypt_body(
+    encrypted: &EncryptedBody,
+    key: &ContentKey,
+    author: &AccountId,
+) -> Option<String> {
+    if encrypted.nonce.0.len() != NONCE_LENGTH {
+        return None;
+    }
+
+    let body = ChaCha20Poly1305::new(Key::from_slice(key))
+        .decrypt(
+            Nonce::from_slice(&encrypted.nonce.0),
+            Payload {
+                msg: encrypted.ciphertext.0.as_slice(),
+                aad: &associated_data(author, &encrypted.context.0),
+            },
+        )
+        .ok()?;
+
+    String::from_utf8(body).ok()
+}
+
+// Wraps `key` for `recipient` under a key agreed between a one-off key pair
+// and the public key of the recipient
+pub fn seal_key(
+    key: &ContentKey,
+    recipient: AccountId,
+    public_key: &PublicKey,
+) -> KeyEnvelope {
+    let ephemeral_secret = EphemeralSecret::random_from_rng(OsRng);
+    let ephemeral_public_key = PublicKey::from(&ephemeral_secret);
+
+    let shared_secret = ephemeral_secret.diffie_hellman(public_key);
+    let wrapping_key = wrapping_key(
+        shared_secret.as_bytes(),
+        &ephemeral_public_key,
+        public_key,
+    );
+
+    let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+
+    let wrapped_key = ChaCha20Poly1305::new(&wrapping_key)
+        .encrypt(&nonce, key.as_slice())
+        .expect("encryption failure");
+
+    KeyEnvelope {
+        recipient,
+        ephemeral_public_key: ephemeral_public_key.as_bytes().to_vec().into(),
+        nonce: nonce.to_vec().into(),
+        wrapped_key: wrapped_key.into(),
+    }
+}
+
+// `None` if the envelope wasn't sealed for `secret`
+pub fn open_key(
+    envelope: &KeyEnvelope,
+    secret: &StaticSecret,
+) -> Option<ContentKey> {
+    let ephemeral_public_key: [u8; KEY_LENGTH] =
+        envelope.ephemeral_public_key.0.as_slice().try_into().ok()?;
+    let ephemeral_public_key = PublicKey::from(ephemeral_public_key);
+
+    if envelope.nonce.0.len() != NONCE_LENGTH {
+        return None;
+    }
+
+    let shared_secret = secret.diffie_hellman(&ephemeral_public_key);
+    let wrapping_key = wrapping_key(
+        shared_secret.as_bytes(),
+        &ephemeral_public_key,
+        &PublicKey::from(secret),
+    );
+
+    let key = ChaCha20Poly1305::new(&wrapping_key)
+        .decrypt(
+            Nonce::from_slice(&envelope.nonce.0),
+            envelope.wrapped_key.0.as_slice(),
+        )
+        .ok()?;
+
+    key.try_into().ok()
+}
+
+// Checks content fetched from the CID or URL of a note against its sha256
+pub fn verify_content(content: &[u8], content_ref: &ContentRef) -> bool {
+    let hash: CryptoHash = Sha256::digest(content).into();
+
+    hash == CryptoHash::from(content_ref.sha256)
+}
+
+// The raw shared secret isn't used as a key directly, it's hashed along with
+// both public keys
+fn wrapping_key(
+    shared_secret: &[u8],
+    ephemeral_public_key: &PublicKey,
+    public_key: &PublicKey,
+) -> Key {
+    let mut hasher = Sha256::new();
+    hasher.update(shared_secret);
+    hasher.update(ephemeral_public_key.as_bytes());
+    hasher.update(public_key.as_bytes());
+
+    Key::clone_from_slice(&hasher.finalize())
+}
+
+// Binds a body to its author and the context of its note. The context has a
+// fixed length, so the two can't run into each other
+fn associated_data(author: &AccountId, context: &[u8]) -> Vec<u8> {
+    [author.as_str().as_bytes(), context].concat()
+}
@@ -0,0 +1,525 @@
+use near_sdk::{
+    env,
+    json_types::{Base64VecU8, U64},
+    near,
+    store::IterableMap,
+    AccountId, FunctionError,
+};
+
+use crate::{
+    content::NoteContent,
+    errors::{ensure, ContractError},
+    pausable::{Feature, Pausable},
+    tags::assert_valid_tags,
+    Contract, ContractExt, PostedNote, StorageKey,
+};
+
+// Recipients a single encrypted note can have, bounded so that removing the
+// note can always drop their keys
+pub(crate) const MAX_RECIPIENTS: u32 = 50;
+
+pub(crate) const KEY_LENGTH: usize = 32;
+pub(crate) const NONCE_LENGTH: usize = 12;
+pub(crate) const TAG_LENGTH: usize = 16;
+pub(crate) const CONTEXT_LENGTH: usize = 16;
+
+// How an encrypted body was produced. Bodies are encrypted off chain with a
+// random content key, which is then wrapped for every recipient
+#[near(serializers = [borsh, json])]
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum EncryptionScheme {
+    // The content key is wrapped with ChaCha20-Poly1305 under an X25519 key
+    // agreed with the recipient, and the body is encrypted with it using
+    // ChaCha20-Poly1305 as well, with the author and context of the note as
+    // associated data
+    X25519ChaCha20Poly1305,
+}
+
+#[near(serializers = [borsh, json])]
+#[derive(Clone, PartialEq, Eq)]
+pub struct EncryptedBody {
+    pub scheme: EncryptionScheme,
+    // Random bytes picked by the author when the note is created, which every
+    // body of the note is bound to so that it can't be passed off as the body
+    // of another note
+    pub context: Base64VecU8,
+    pub nonce: Base64VecU8,
+    pub ciphertext: Base64VecU8,
+}
+
+impl EncryptedBody {
+    pub(crate) fn assert_valid(&self) {
+        ensure!(
+            self.context.0.len() == CONTEXT_LENGTH
+                && self.nonce.0.len() == NONCE_LENGTH
+                && self.ciphertext.0.len() >= TAG_LENGTH,
+            ContractError::InvalidCiphertext
+        );
+    }
+}
+
+// The content key of a note, wrapped for `recipient`
+#[near(serializers = [borsh, json])]
+#[derive(Clone)]
+pub struct KeyEnvelope {
+    pub recipient: AccountId,
+    // Public half of the one-off key pair the content key was wrapped with
+    pub ephemeral_public_key: Base64VecU8,
+    pub nonce: Base64VecU8,
+    pub wrapped_key: Base64VecU8,
+}
+
+impl KeyEnvelope {
+    fn assert_valid(&self) {
+        ensure!(
+            self.ephemeral_public_key.0.len() == KEY_LENGTH
+                && self.nonce.0.len() == NONCE_LENGTH
+                && self.wrapped_key.0.len() == KEY_LENGTH + TAG_LENGTH,
+            ContractError::InvalidEnvelope
+        );
+    }
+}
+
+#[near]
+impl Contract {
+    // Like `add_note`, but the body is only stored encrypted. The author
+    // should include an envelope for themselves to be able to read it back
+    #[payable]
+    pub fn add_encrypted_note(
+        &mut self,
+        title: String,
+        encrypted: EncryptedBody,
+        envelopes: Vec<KeyEnvelope>,
+        tags: Option<Vec<String>>,
+    ) -> U64 {
+        self.assert_feature_not_paused(Feature::Notes);
+
+        encrypted.assert_valid();
+
+        let account_id = env::predecessor_account_id();
+
+        let mut note = PostedNote::new(
+            title,
+            String::new(),
+            None,
+            account_id.clone(),
+            tags.unwrap_or_default(),
+        );
+        note.encrypted = Some(encrypted);
+
+        let id = self.internal_add_note(
+            account_id.clone(),
+            note,
+            env::attached_deposit().as_yoctonear(),
+        );
+
+        let storage_usage = env::storage_usage();
+
+        for envelope in envelopes {
+            self.internal_set_note_key(&account_id, id, envelope);
+        }
+
+        self.internal_settle_storage(&account_id, storage_usage);
+
+        U64(id)
+    }
+
+    // Like `update_note`, the content key and context have to stay the same
+    // for the existing envelopes to open the new body, e.g. with
+    // `crypto::encrypt_body_with_key`
+    #[payable]
+    pub fn update_encrypted_note(
+        &mut self,
+        id: U64,
+        title: String,
+        encrypted: EncryptedBody,
+        tags: Option<Vec<String>>,
+        author: Option<AccountId>,
+    ) {
+        self.assert_feature_not_paused(Feature::Notes);
+        self.assert_not_migrating();
+
+        encrypted.assert_valid();
+
+        if let Some(tags) = &tags {
+            assert_valid_tags(tags);
+        }
+
+        let account_id = author.unwrap_or_else(env::predecessor_account_id);
+
+        self.assert_can_write(&account_id, id.0);
+
+        let deposit = env::attached_deposit().as_yoctonear();
+
+        if deposit != 0 {
+            self.internal_storage_deposit(&account_id, deposit);
+        }
+
+        self.internal_update_note(
+            account_id,
+            id,
+            title,
+            NoteContent::encrypted(encrypted),
+            tags,
+        );
+    }
+
+    // The author adds or replaces the key of a recipient, e.g. after sharing
+    // the note with them
+    #[payable]
+    pub fn set_note_key(&mut self, id: U64, envelope: KeyEnvelope) {
+        self.assert_feature_not_paused(Feature::Notes);
+
+        let account_id = env::predecessor_account_id();
+
+        let note = self
+            .notes
+            .get(&(account_id.clone(), id.0))
+            .unwrap_or_else(|| ContractError::NoteNotFound.panic());
+
+        ensure!(note.encrypted.is_some(), ContractError::NotEncrypted);
+
+        let deposit = env::attached_deposit().as_yoctonear();
+
+        if deposit != 0 {
+            self.internal_storage_deposit(&account_id, deposit);
+        }
+
+        let storage_usage = env::storage_usage();
+
+        self.internal_set_note_key(&account_id, id.0, envelope);
+
+        self.internal_settle_storage(&account_id, storage_usage);
+    }
+
+    pub fn remove_note_key(&mut self, id: U64, recipient: AccountId) {
+        let account_id = env::predecessor_account_id();
+
+        let storage_usage = env::storage_usage();
+
+        let envelopes = self
+            .note_keys
+            .get_mut(&(account_id.clone(), id.0))
+            .filter(|envelopes| envelopes.contains_key(&recipient))
+            .unwrap_or_else(|| ContractError::KeyNotFound.panic());
+
+        envelopes.remove(&recipient);
+        envelopes.flush();
+
+        if envelopes.is_empty() {
+            self.note_keys.remove(&(account_id.clone(), id.0));
+        }
+
+        self.note_keys.flush();
+
+        self.internal_settle_storage(&account_id, storage_usage);
+    }
+
+    // Registers the X25519 public key others wrap content keys for the caller
+    // with. It's paid for from the storage balance of the caller
+    #[payable]
+    pub fn set_encryption_key(&mut self, public_key: Base64VecU8) {
+        ensure!(public_key.0.len() == KEY_LENGTH, ContractError::InvalidKey);
+
+        let account_id = env::predecessor_account_id();
+
+        let deposit = env::attached_deposit().as_yoctonear();
+
+        if deposit != 0 {
+            self.internal_storage_deposit(&account_id, deposit);
+        }
+
+        let storage_usage = env::storage_usage();
+
+        self.encryption_keys.insert(account_id.clone(), public_key);
+        self.encryption_keys.flush();
+
+        self.internal_settle_storage(&account_id, storage_usage);
+    }
+
+    pub fn get_encryption_key(
+        &self,
+        account_id: AccountId,
+    ) -> Option<&Base64VecU8> {
+        self.encryption_keys.get(&account_id)
+    }
+
+    pub fn get_note_key(
+        &self,
+        account_id: AccountId,
+        id: U64,
+        recipient: AccountId,
+    ) -> Option<&KeyEnvelope> {
+        self.note_keys
+            .get(&(account_id, id.0))
+            .and_then(|envelopes| envelopes.get(&recipient))
+    }
+
+    pub fn get_note_keys(
+        &self,
+        account_id: AccountId,
+        id: U64,
+        from_index: Option<u32>,
+        limit: Option<u32>,
+    ) -> Vec<&KeyEnvelope> {
+        self.note_keys
+            .get(&(account_id, id.0))
+            .map_or(vec![], |envelopes| {
+                envelopes
+                    .values()
+                    .skip(from_index.unwrap_or(0) as usize)
+                    .take(limit.unwrap_or(u32::MAX) as usize)
+                    .collect()
+            })
+    }
+
+    // Drops every key of note `id`, called once the note is removed
+    pub(crate) fn internal_remove_note_keys(
+        &mut self,
+        account_id: &AccountId,
+        id: u64,
+    ) {
+        if let Some(mut envelopes) =
+            self.note_keys.remove(&(account_id.clone(), id))
+        {
+            envelopes.clear();
+            envelopes.flush();
+        }
+
+        self.note_keys.flush();
+    }
+
+    fn internal_set_note_key(
+        &mut self,
+        account_id: &AccountId,
+        id: u64,
+        envelope: KeyEnvelope,
+    ) {
+        envelope.assert_valid();
+
+        let key = (account_id.clone(), id);
+
+        if let Some(envelopes) = self.note_keys.get_mut(&key) {
+            envelopes.insert(envelope.recipient.clone(), envelope);
+            ensure!(
+                envelopes.len() <= MAX_RECIPIENTS,
+                ContractError::TooManyRecipients
+            );
+            envelopes.flush();
+        } else {
+            let mut envelopes = IterableMap::new(StorageKey::NoteKeyEnvelopes(
+                account_id.clone(),
+                id,
+            ));
+            envelopes.insert(envelope.recipient.clone(), envelope);
+            envelopes.flush();
+
+            self.note_keys.insert(key, envelopes);
+        }
+
+        self.note_keys.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use near_sdk::NearToken;
+
+    use super::*;
+    use crate::{
+        crypto,
+        test_utils::{new_contract, set_context},
+    };
+
+    #[test]
+    fn add_encrypted_note() {
+        let mut contract = new_contract();
+
+        let account_id: AccountId = "account_id1".parse().unwrap();
+
+        let (_, author_public_key) = crypto::generate_keypair();
+        let (reader_secret, reader_public_key) = crypto::generate_keypair();
+
+        let (encrypted, content_key) =
+            crypto::encrypt_body("secret body", &account_id);
+
+        let envelopes = vec![
+            crypto::seal_key(
+                &content_key,
+                account_id.clone(),
+                &author_public_key,
+            ),
+            crypto::seal_key(
+                &content_key,
+                "account_id2".parse().unwrap(),
+                &reader_public_key,
+            ),
+        ];
+
+        set_context("account_id1", NearToken::from_near(1));
+        let id = contract.add_encrypted_note(
+            "title".into(),
+            encrypted,
+            envelopes,
+            None,
+        );
+
+        let note = contract.get_note(account_id.clone(), id);
+        assert!(note.body.is_empty());
+
+        let envelope = contract
+            .get_note_key(
+                account_id.clone(),
+                id,
+                "account_id2".parse().unwrap(),
+            )
+            .unwrap();
+        let key = crypto::open_key(envelope, &reader_secret).unwrap();
+
+        let encrypted = note.encrypted.as_ref().unwrap();
+        assert_eq!(
+            crypto::decrypt_body(encrypted, &key, &account_id).unwrap(),
+            "secret body"
+        );
+
+        // the body only opens for its author and the context of its note
+        assert!(crypto::decrypt_body(
+            encrypted,
+            &key,
+            &"account_id2".parse().unwrap()
+        )
+        .is_none());
+
+        let mut moved = encrypted.clone();
+        moved.context = vec![0; CONTEXT_LENGTH].into();
+        assert!(crypto::decrypt_body(&moved, &key, &account_id).is_none());
+
+        // the reader can't open the key of the author
+        let envelope = contract
+            .get_note_key(account_id.clone(), id, account_id.clone())
+            .unwrap();
+        assert!(crypto::open_key(envelope, &reader_secret).is_none());
+
+        contract.remove_note(id, None);
+
+        assert!(contract
+            .get_note_keys(account_id, id, None, None)
+            .is_empty());
+    }
+
+    #[test]
+    fn update_encrypted_note() {
+        let mut contract = new_contract();
+
+        let account_id: AccountId = "account_id1".parse().unwrap();
+
+        let (secret, public_key) = crypto::generate_keypair();
+
+        let (encrypted, content_key) =
+            crypto::encrypt_body("secret body", &account_id);
+        let context = encrypted.context.clone();
+
+        set_context("account_id1", NearToken::from_near(1));
+        let id = contract.add_encrypted_note(
+            "title".into(),
+            encrypted,
+            vec![crypto::seal_key(
+                &content_key,
+                account_id.clone(),
+                &public_key,
+            )],
+            None,
+        );
+
+        let encrypted = crypto::encrypt_body_with_key(
+            &content_key,
+            "new body",
+            &account_id,
+            &context.0,
+        );
+
+        contract.update_encrypted_note(
+            id,
+            "title".into(),
+            encrypted,
+            None,
+            None,
+        );
+
+        // the envelope sealed for the first body opens the new one
+        let envelope = contract
+            .get_note_key(account_id.clone(), id, account_id.clone())
+            .unwrap();
+        let key = crypto::open_key(envelope, &secret).unwrap();
+
+        let note = contract.get_note(account_id.clone(), id);
+        assert_eq!(
+            crypto::decrypt_body(
+                note.encrypted.as_ref().unwrap(),
+                &key,
+                &account_id
+            )
+            .unwrap(),
+            "new body"
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_ENCRYPTION_MISMATCH")]
+    fn update_encrypted_note_other_context() {
+        let mut contract = new_contract();
+
+        set_context("account_id1", NearToken::from_near(1));
+        let id = new_encrypted_note(&mut contract, "account_id1");
+
+        // the body of another note can't be moved into this one
+        let (encrypted, _) =
+            crypto::encrypt_body("other body", &"account_id1".parse().unwrap());
+
+        contract.update_encrypted_note(
+            id,
+            "title".into(),
+            encrypted,
+            None,
+            None,
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_ENCRYPTION_MISMATCH")]
+    fn update_encrypted_note_in_plain() {
+        let mut contract = new_contract();
+
+        set_context("account_id1", NearToken::from_near(1));
+        let id = new_encrypted_note(&mut contract, "account_id1");
+
+        contract.update_note(id, "title".into(), "body".into(), None, None);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_INVALID_ENVELOPE")]
+    fn set_invalid_note_key() {
+        let mut contract = new_contract();
+
+        set_context("account_id1", NearToken::from_near(1));
+        let id = new_encrypted_note(&mut contract, "account_id1");
+
+        contract.set_note_key(
+            id,
+            KeyEnvelope {
+                recipient: "account_id2".parse().unwrap(),
+                ephemeral_public_key: vec![0; KEY_LENGTH].into(),
+                nonce: vec![0; NONCE_LENGTH].into(),
+                wrapped_key: vec![0; KEY_LENGTH].into(),
+            },
+        );
+    }
+
+    // Adds an encrypted note of `account_id` without recipients
+    fn new_encrypted_note(contract: &mut Contract, account_id: &str) -> U64 {
+        let (encrypted, _) =
+            crypto::encrypt_body("secret body", &account_id.parse().unwrap());
+
+        contract.add_encrypted_note("title".into(), encrypted, vec![], None)
+    }
+}
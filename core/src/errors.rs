@@ -0,0 +1,210 @@
+use std::fmt::{self, Display, Formatter};
+
+use near_sdk::{env, FunctionError};
+
+// `require!` with a `ContractError` in place of the message
+macro_rules! ensure {
+    ($cond:expr, $error:expr $(,)?) => {
+        if !$cond {
+            near_sdk::FunctionError::panic(&$error)
+        }
+    };
+}
+
+pub(crate) use ensure;
+
+// Every error the contract fails with. Calls fail with the code of the error
+// as the panic message, and codes never change once released so that clients
+// can match on them
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ContractError {
+    // Notes
+    NoteNotFound,
+    NoteBookNotFound,
+    NoPoints,
+    NotEnoughPoints,
+    EncryptionMismatch,
+    ContentRefMismatch,
+    EmptyTitle,
+    TitleTooLong,
+    InvalidTitle,
+    BodyTooLong,
+    InvalidBody,
+    InvalidLimits,
+    TooManyTags,
+    InvalidTag,
+    DuplicateTag,
+    RevisionNotFound,
+    InvalidContentRef,
+    InvalidAttachment,
+    TooManyAttachments,
+    DuplicateAttachment,
+    AttachmentNotFound,
+    // Encryption
+    InvalidCiphertext,
+    InvalidEnvelope,
+    NotEncrypted,
+    KeyNotFound,
+    InvalidKey,
+    TooManyRecipients,
+    // Sharing
+    SelfGrant,
+    GrantNotFound,
+    NoAccess,
+    TooManyGrantees,
+    // Workspaces
+    WorkspaceNotFound,
+    InvalidName,
+    NotMember,
+    AlreadyMember,
+    AlreadyInvited,
+    NotInvited,
+    Owner,
+    // Storage
+    BelowMinBalance,
+    NotRegistered,
+    NotEnoughBalance,
+    NotEnoughDeposit,
+    HasNotes,
+    InvalidAccountId,
+    // Tokens
+    InvalidMessage,
+    TokenNotSupported,
+    MinAmount,
+    InvalidRate,
+    AmountTooLarge,
+    // Roles and pausing
+    NoManagers,
+    NotManager,
+    MissingRole,
+    RoleNotFound,
+    LastAdmin,
+    Paused,
+    // Timelock
+    ActionNotFound,
+    Timelocked,
+    InvalidThreshold,
+    // Upgrades
+    NoInput,
+    AlreadyStaged,
+    AlreadyApproved,
+    NotEnoughApprovals,
+    ProposalNotFound,
+    ProposalExpired,
+    // Migrations
+    NoState,
+    UnknownState,
+    Downgrade,
+    NoMigration,
+    MigrationInProgress,
+    MissingNoteId,
+}
+
+impl ContractError {
+    pub const fn code(&self) -> &'static str {
+        match self {
+            ContractError::NoteNotFound => "ERR_NOTE_NOT_FOUND",
+            ContractError::NoteBookNotFound => "ERR_NOTE_BOOK_NOT_FOUND",
+            ContractError::NoPoints => "ERR_NO_POINTS",
+            ContractError::NotEnoughPoints => "ERR_NOT_ENOUGH_POINTS",
+            ContractError::EncryptionMismatch => "ERR_ENCRYPTION_MISMATCH",
+            ContractError::ContentRefMismatch => "ERR_CONTENT_REF_MISMATCH",
+            ContractError::EmptyTitle => "ERR_EMPTY_TITLE",
+            ContractError::TitleTooLong => "ERR_TITLE_TOO_LONG",
+            ContractError::InvalidTitle => "ERR_INVALID_TITLE",
+            ContractError::BodyTooLong => "ERR_BODY_TOO_LONG",
+            ContractError::InvalidBody => "ERR_INVALID_BODY",
+            ContractError::InvalidLimits => "ERR_INVALID_LIMITS",
+            ContractError::TooManyTags => "ERR_TOO_MANY_TAGS",
+            ContractError::InvalidTag => "ERR_INVALID_TAG",
+            ContractError::DuplicateTag => "ERR_DUPLICATE_TAG",
+            ContractError::RevisionNotFound => "ERR_REVISION_NOT_FOUND",
+            ContractError::InvalidContentRef => "ERR_INVALID_CONTENT_REF",
+            ContractError::InvalidAttachment => "ERR_INVALID_ATTACHMENT",
+            ContractError::TooManyAttachments => "ERR_TOO_MANY_ATTACHMENTS",
+            ContractError::DuplicateAttachment => "ERR_DUPLICATE_ATTACHMENT",
+            ContractError::AttachmentNotFound => "ERR_ATTACHMENT_NOT_FOUND",
+            ContractError::InvalidCiphertext => "ERR_INVALID_CIPHERTEXT",
+            ContractError::InvalidEnvelope => "ERR_INVALID_ENVELOPE",
+            ContractError::NotEncrypted => "ERR_NOT_ENCRYPTED",
+            ContractError::KeyNotFound => "ERR_KEY_NOT_FOUND",
+            ContractError::InvalidKey => "ERR_INVALID_KEY",
+            ContractError::TooManyRecipients => "ERR_TOO_MANY_RECIPIENTS",
+            ContractError::SelfGrant => "ERR_SELF_GRANT",
+            ContractError::GrantNotFound => "ERR_GRANT_NOT_FOUND",
+            ContractError::NoAccess => "ERR_NO_ACCESS",
+            ContractError::TooManyGrantees => "ERR_TOO_MANY_GRANTEES",
+            ContractError::WorkspaceNotFound => "ERR_WORKSPACE_NOT_FOUND",
+            ContractError::InvalidName => "ERR_INVALID_NAME",
+            ContractError::NotMember => "ERR_NOT_MEMBER",
+            ContractError::AlreadyMember => "ERR_ALREADY_MEMBER",
+            ContractError::AlreadyInvited => "ERR_ALREADY_INVITED",
+            ContractError::NotInvited => "ERR_NOT_INVITED",
+            ContractError::Owner => "ERR_OWNER",
+            ContractError::BelowMinBalance => "ERR_BELOW_MIN_BALANCE",
+            ContractError::NotRegistered => "ERR_NOT_REGISTERED",
+            ContractError::NotEnoughBalance => "ERR_NOT_ENOUGH_BALANCE",
+            ContractError::NotEnoughDeposit => "ERR_NOT_ENOUGH_DEPOSIT",
+            ContractError::HasNotes => "ERR_HAS_NOTES",
+            ContractError::InvalidAccountId => "ERR_INVALID_ACCOUNT_ID",
+            ContractError::InvalidMessage => "ERR_INVALID_MESSAGE",
+            ContractError::TokenNotSupported => "ERR_TOKEN_NOT_SUPPORTED",
+            ContractError::MinAmount => "ERR_MIN_AMOUNT",
+            ContractError::InvalidRate => "ERR_INVALID_RATE",
+            ContractError::AmountTooLarge => "ERR_AMOUNT_TOO_LARGE",
+            ContractError::NoManagers => "ERR_NO_MANAGERS",
+            ContractError::NotManager => "ERR_NOT_MANAGER",
+            ContractError::MissingRole => "ERR_MISSING_ROLE",
+            ContractError::RoleNotFound => "ERR_ROLE_NOT_FOUND",
+            ContractError::LastAdmin => "ERR_LAST_ADMIN",
+            ContractError::Paused => "ERR_PAUSED",
+            ContractError::ActionNotFound => "ERR_ACTION_NOT_FOUND",
+            ContractError::Timelocked => "ERR_TIMELOCKED",
+            ContractError::InvalidThreshold => "ERR_INVALID_THRESHOLD",
+            ContractError::NoInput => "ERR_NO_INPUT",
+            ContractError::AlreadyStaged => "ERR_ALREADY_STAGED",
+            ContractError::AlreadyApproved => "ERR_ALREADY_APPROVED",
+            ContractError::NotEnoughApprovals => "ERR_NOT_ENOUGH_APPROVALS",
+            ContractError::ProposalNotFound => "ERR_PROPOSAL_NOT_FOUND",
+            ContractError::ProposalExpired => "ERR_PROPOSAL_EXPIRED",
+            ContractError::NoState => "ERR_NO_STATE",
+            ContractError::UnknownState => "ERR_UNKNOWN_STATE",
+            ContractError::Downgrade => "ERR_DOWNGRADE",
+            ContractError::NoMigration => "ERR_NO_MIGRATION",
+            ContractError::MigrationInProgress => "ERR_MIGRATION_IN_PROGRESS",
+            ContractError::MissingNoteId => "ERR_MISSING_NOTE_ID",
+        }
+    }
+}
+
+impl Display for ContractError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str(self.code())
+    }
+}
+
+impl FunctionError for ContractError {
+    fn panic(&self) -> ! {
+        env::panic_str(self.code())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[should_panic(expected = "ERR_NOTE_NOT_FOUND")]
+    fn panic_with_code() {
+        ContractError::NoteNotFound.panic();
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_NO_ACCESS")]
+    fn ensure_fails_with_code() {
+        let grants: Vec<u64> = vec![];
+
+        ensure!(grants.is_empty(), ContractError::NoteNotFound);
+        ensure!(!grants.is_empty(), ContractError::NoAccess);
+    }
+}
@@ -0,0 +1,242 @@
+use near_sdk::{
+    json_types::{Base58CryptoHash, U128, U64},
+    near, AccountId,
+};
+
+use crate::{
+    ownership::Role, pausable::Feature, sharing::Permission, timelock::Action,
+    workspace::WorkspaceRole,
+};
+
+// Events are logged as `EVENT_JSON:{"standard":"notebook","version":"1.0.0",
+// "event":"note_added","data":{..}}` following NEP-297
+#[near(event_json(standard = "notebook"))]
+pub enum NotebookEvent {
+    #[event_version("1.0.0")]
+    NoteAdded(NoteAdded),
+    #[event_version("1.0.0")]
+    NoteUpdated(NoteUpdated),
+    #[event_version("1.0.0")]
+    NoteRemoved(NoteRemoved),
+    #[event_version("1.0.0")]
+    PointsChanged(PointsChanged),
+    #[event_version("1.0.0")]
+    ExchangeStarted(ExchangeStarted),
+    #[event_version("1.0.0")]
+    ExchangeResolved(ExchangeResolved),
+    #[event_version("1.0.0")]
+    RoleGranted(RoleGranted),
+    #[event_version("1.0.0")]
+    RoleRevoked(RoleRevoked),
+    #[event_version("1.0.0")]
+    Paused(Paused),
+    #[event_version("1.0.0")]
+    Migrated(Migrated),
+    #[event_version("1.0.0")]
+    CodeStaged(CodeStaged),
+    #[event_version("1.0.0")]
+    CodeApproved(CodeApproved),
+    #[event_version("1.0.0")]
+    CodeRemoved(CodeRemoved),
+    #[event_version("1.0.0")]
+    Upgraded(Upgraded),
+    #[event_version("1.0.0")]
+    ActionQueued(ActionQueued),
+    #[event_version("1.0.0")]
+    ActionCancelled(ActionCancelled),
+    #[event_version("1.0.0")]
+    ActionExecuted(ActionExecuted),
+    #[event_version("1.0.0")]
+    AccessGranted(AccessGranted),
+    #[event_version("1.0.0")]
+    AccessRevoked(AccessRevoked),
+    #[event_version("1.0.0")]
+    WorkspaceCreated(WorkspaceCreated),
+    #[event_version("1.0.0")]
+    WorkspaceDeleted(WorkspaceDeleted),
+    #[event_version("1.0.0")]
+    MemberInvited(MemberInvited),
+    #[event_version("1.0.0")]
+    MemberJoined(MemberJoined),
+    #[event_version("1.0.0")]
+    MemberRemoved(MemberRemoved),
+    #[event_version("1.0.0")]
+    MemberRoleChanged(MemberRoleChanged),
+}
+
+// `workspace_id` is only set for the notes of a workspace
+#[near(serializers = [json])]
+pub struct NoteAdded {
+    pub account_id: AccountId,
+    pub id: U64,
+    pub title: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub workspace_id: Option<U64>,
+}
+
+#[near(serializers = [json])]
+pub struct NoteUpdated {
+    pub account_id: AccountId,
+    pub id: U64,
+    pub title: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub workspace_id: Option<U64>,
+}
+
+#[near(serializers = [json])]
+pub struct NoteRemoved {
+    pub account_id: AccountId,
+    pub id: U64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub workspace_id: Option<U64>,
+}
+
+// `points` is the new total of the account
+#[near(serializers = [json])]
+pub struct PointsChanged {
+    pub account_id: AccountId,
+    pub points: U128,
+}
+
+#[near(serializers = [json])]
+pub struct ExchangeStarted {
+    pub account_id: AccountId,
+    pub token_in: AccountId,
+    pub token_out: AccountId,
+    pub amount_out: U128,
+}
+
+#[near(serializers = [json])]
+pub struct ExchangeResolved {
+    pub account_id: AccountId,
+    pub amount: U128,
+    pub success: bool,
+}
+
+#[near(serializers = [json])]
+pub struct RoleGranted {
+    pub account_id: AccountId,
+    pub role: Role,
+}
+
+#[near(serializers = [json])]
+pub struct RoleRevoked {
+    pub account_id: AccountId,
+    pub role: Role,
+}
+
+// `feature` is `None` when the whole contract is (un)paused
+#[near(serializers = [json])]
+pub struct Paused {
+    pub account_id: AccountId,
+    pub feature: Option<Feature>,
+    pub paused: bool,
+}
+
+#[near(serializers = [json])]
+pub struct Migrated {
+    pub from_version: U64,
+    pub to_version: U64,
+}
+
+#[near(serializers = [json])]
+pub struct CodeStaged {
+    pub account_id: AccountId,
+    pub hash: Base58CryptoHash,
+}
+
+#[near(serializers = [json])]
+pub struct CodeApproved {
+    pub account_id: AccountId,
+    pub hash: Base58CryptoHash,
+}
+
+#[near(serializers = [json])]
+pub struct CodeRemoved {
+    pub account_id: AccountId,
+    pub hash: Base58CryptoHash,
+}
+
+#[near(serializers = [json])]
+pub struct Upgraded {
+    pub hash: Base58CryptoHash,
+    pub version: U64,
+    pub success: bool,
+}
+
+#[near(serializers = [json])]
+pub struct ActionQueued {
+    pub account_id: AccountId,
+    pub id: U64,
+    pub action: Action,
+    pub execute_after: U64,
+}
+
+#[near(serializers = [json])]
+pub struct ActionCancelled {
+    pub account_id: AccountId,
+    pub id: U64,
+}
+
+#[near(serializers = [json])]
+pub struct ActionExecuted {
+    pub account_id: AccountId,
+    pub id: U64,
+}
+
+// `id` is `None` when access is given to the whole note book of `account_id`
+#[near(serializers = [json])]
+pub struct AccessGranted {
+    pub account_id: AccountId,
+    pub grantee: AccountId,
+    pub id: Option<U64>,
+    pub permission: Permission,
+}
+
+#[near(serializers = [json])]
+pub struct AccessRevoked {
+    pub account_id: AccountId,
+    pub grantee: AccountId,
+    pub id: Option<U64>,
+}
+
+#[near(serializers = [json])]
+pub struct WorkspaceCreated {
+    pub account_id: AccountId,
+    pub workspace_id: U64,
+    pub name: String,
+}
+
+#[near(serializers = [json])]
+pub struct WorkspaceDeleted {
+    pub account_id: AccountId,
+    pub workspace_id: U64,
+}
+
+#[near(serializers = [json])]
+pub struct MemberInvited {
+    pub account_id: AccountId,
+    pub workspace_id: U64,
+    pub invitee: AccountId,
+}
+
+#[near(serializers = [json])]
+pub struct MemberJoined {
+    pub account_id: AccountId,
+    pub workspace_id: U64,
+}
+
+#[near(serializers = [json])]
+pub struct MemberRemoved {
+    pub account_id: AccountId,
+    pub workspace_id: U64,
+    pub member: AccountId,
+}
+
+#[near(serializers = [json])]
+pub struct MemberRoleChanged {
+    pub account_id: AccountId,
+    pub workspace_id: U64,
+    pub member: AccountId,
+    pub role: WorkspaceRole,
+}
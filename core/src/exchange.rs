@@ -1,19 +1,39 @@
 use near_sdk::{
-    ext_contract, is_promise_success, json_types::U128, log, near, AccountId,
-    NearToken, Promise, PromiseOrValue,
+    env, ext_contract, is_promise_success,
+    json_types::{U128, U64},
+    near, AccountId, FunctionError, NearToken, Promise, PromiseOrValue,
 };
 
-use crate::{ft_receiver::Token, Contract, ContractExt};
+use crate::{
+    errors::ContractError,
+    events::{ExchangeResolved, ExchangeStarted, NotebookEvent},
+    ft_receiver::Token,
+    pausable::{Feature, Pausable},
+    timelock::Action,
+    Contract, ContractExt,
+};
+
+// 1 Near = 4 FungibleToken until a rate setter changes it
+pub(crate) const DEFAULT_EXCHANGE_RATE: u128 = 4;
 
 #[ext_contract(ext_ft)]
 pub trait ExtFt {
     fn ft_transfer(&mut self, receiver_id: AccountId, amount: U128);
 }
 
-// Simple exchange rate
-// 1 Near = 4 FungibleToken
 #[near]
 impl Contract {
+    // Rate changes land through the timelock. Queues the change and returns
+    // the id of the queued action
+    pub fn set_exchange_rate(&mut self, rate: U128) -> U64 {
+        self.queue_action(Action::SetExchangeRate { rate })
+    }
+
+    // FungibleToken given for one Near
+    pub fn get_exchange_rate(&self) -> U128 {
+        U128(self.exchange_rate)
+    }
+
     pub(crate) fn internal_exchange(
         &mut self,
         sender_id: AccountId,
@@ -21,20 +41,31 @@ impl Contract {
         token_out: Token,
         amount_out: u128,
     ) -> Promise {
+        self.assert_feature_not_paused(Feature::Exchange);
+
         let destination = AccountId::from(token_out);
 
         if token_in == Token::Near {
-            self.user_points
-                .entry(sender_id.clone())
-                .and_modify(|points| points.0 += amount_out)
-                .or_insert(amount_out.into());
+            self.internal_update_points(sender_id.clone(), |points| {
+                points.unwrap_or(0) + amount_out
+            });
         } else {
-            self.user_points
-                .entry(sender_id.clone())
-                .and_modify(|points| points.0 -= amount_out)
-                .or_insert(0.into());
+            self.internal_update_points(sender_id.clone(), |points| {
+                points
+                    .unwrap_or(0)
+                    .checked_sub(amount_out)
+                    .unwrap_or_else(|| ContractError::NotEnoughPoints.panic())
+            });
         }
 
+        NotebookEvent::ExchangeStarted(ExchangeStarted {
+            account_id: sender_id.clone(),
+            token_in: env::predecessor_account_id(),
+            token_out: destination.clone(),
+            amount_out: amount_out.into(),
+        })
+        .emit();
+
         ext_ft::ext(destination.clone())
             .with_attached_deposit(NearToken::from_yoctonear(1))
             .ft_transfer(sender_id, amount_out.into())
@@ -47,25 +78,78 @@ impl Contract {
         token_in: Token,
         amount: U128,
     ) -> PromiseOrValue<U128> {
-        if is_promise_success() {
-            log!("Exchange successful");
+        let success = is_promise_success();
+
+        NotebookEvent::ExchangeResolved(ExchangeResolved {
+            account_id: sender_id.clone(),
+            amount,
+            success,
+        })
+        .emit();
+
+        if success {
             PromiseOrValue::Value(amount)
         } else {
-            log!("Exchange failed");
-
+            // Points may have been spent in the meantime, and a failed
+            // exchange must not fail the callback
             if token_in == Token::Near {
-                self.user_points
-                    .entry(sender_id)
-                    .and_modify(|points| points.0 -= amount.0)
-                    .or_insert(0.into());
+                self.internal_update_points(sender_id, |points| {
+                    points.unwrap_or(0).saturating_sub(amount.0)
+                });
             } else {
-                self.user_points
-                    .entry(sender_id.clone())
-                    .and_modify(|points| points.0 += amount.0)
-                    .or_insert(amount.0.into());
+                self.internal_update_points(sender_id, |points| {
+                    points.unwrap_or(0) + amount.0
+                });
             }
 
             PromiseOrValue::Value(amount)
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use near_sdk::{test_utils::VMContextBuilder, testing_env, PromiseResult};
+
+    use super::*;
+    use crate::test_utils::{new_contract, set_context};
+
+    #[test]
+    #[should_panic(expected = "ERR_NOT_ENOUGH_POINTS")]
+    fn exchange_not_enough_points() {
+        let mut contract = new_contract();
+
+        // tokens are told apart through the stored state
+        env::state_write(&contract);
+
+        set_context("some_acc.near", NearToken::from_near(0));
+        contract.internal_exchange(
+            "account_id1".parse().unwrap(),
+            Token::FungibleToken,
+            Token::Near,
+            1,
+        );
+    }
+
+    #[test]
+    fn resolve_exchange_points_spent() {
+        let mut contract = new_contract();
+
+        let account_id: AccountId = "account_id1".parse().unwrap();
+
+        set_context("some_acc.near", NearToken::from_near(0));
+        contract.internal_update_points(account_id.clone(), |_| 1);
+
+        // the points given for the exchange were spent before it failed
+        testing_env!(
+            VMContextBuilder::new().build(),
+            near_sdk::test_vm_config(),
+            near_sdk::RuntimeFeesConfig::test(),
+            Default::default(),
+            vec![PromiseResult::Failed],
+        );
+        contract.resolve_exchange(account_id.clone(), Token::Near, U128(4));
+
+        assert_eq!(contract.get_user_points(account_id).0, 0);
+    }
+}
@@ -5,18 +5,54 @@ use std::{
 
 use near_contract_standards::fungible_token::receiver::FungibleTokenReceiver;
 use near_sdk::{
-    env, json_types::U128, near, require, AccountId, NearToken, PromiseOrValue,
+    env, is_promise_success,
+    json_types::{U128, U64},
+    log, near, AccountId, FunctionError, NearToken, Promise, PromiseOrValue,
 };
 
-use crate::{Contract, ContractExt, PostedNote, MIN_NOTE_DEPOSIT};
+use crate::{
+    errors::{ensure, ContractError},
+    exchange::ext_ft,
+    pausable::{Feature, Pausable},
+    Contract, ContractExt, PostedNote, MIN_NOTE_DEPOSIT,
+};
 
+// A note as sent along with a transfer, the id, author and timestamps are
+// filled in by the contract
+#[near(serializers = [json])]
+pub struct NoteMessage {
+    pub title: String,
+    pub body: String,
+    pub tags: Option<Vec<String>>,
+}
+
+// Ids are sent as strings like everywhere else in the JSON API, so a removal
+// is sent as `{"RemoveNote":"5"}`. Numbers were never accepted, removals
+// through a transfer weren't implemented before
 #[near(serializers = [json])]
 pub enum FtMessage {
-    AddNote(PostedNote),
-    RemoveNote(u64),
+    AddNote(NoteMessage),
+    RemoveNote(U64),
     Exchange,
 }
 
+impl FtMessage {
+    // The feature each message falls under when pausing
+    fn feature(&self) -> Feature {
+        match self {
+            FtMessage::AddNote(_) | FtMessage::RemoveNote(_) => Feature::Notes,
+            FtMessage::Exchange => Feature::Exchange,
+        }
+    }
+}
+
+// Tokens a note was paid for with, given back when the note is removed
+#[near(serializers = [borsh])]
+pub struct NoteDeposit {
+    pub token_id: AccountId,
+    pub amount: U128,
+}
+
 #[near(serializers = [json])]
 #[derive(Clone, PartialEq, Eq)]
 pub enum Token {
@@ -59,7 +95,7 @@ impl FromStr for Token {
         } else if s == contract.ft_id {
             Ok(Token::FungibleToken)
         } else {
-            Err("Token not supported".to_string())
+            Err(ContractError::TokenNotSupported.to_string())
         }
     }
 }
@@ -72,62 +108,93 @@ impl FungibleTokenReceiver for Contract {
         amount: U128,
         msg: String,
     ) -> PromiseOrValue<U128> {
+        // Refund the whole transfer instead of panicking while paused
+        if self.is_feature_paused(Feature::FtDeposits) {
+            return PromiseOrValue::Value(amount);
+        }
+
         let ft_message = near_sdk::serde_json::from_str::<FtMessage>(&msg)
-            .expect("Unable to deserialize msg");
+            .unwrap_or_else(|_| ContractError::InvalidMessage.panic());
+
+        if self.is_feature_paused(ft_message.feature()) {
+            return PromiseOrValue::Value(amount);
+        }
 
         let token = env::predecessor_account_id()
             .as_str()
             .parse::<Token>()
-            .expect("Token not supported");
+            .unwrap_or_else(|_| ContractError::TokenNotSupported.panic());
 
         match ft_message {
             FtMessage::AddNote(note) => {
-                require!(
-                    amount.0 >= MIN_NOTE_DEPOSIT,
-                    "Minimum deposit is 1 FT"
+                ensure!(amount.0 >= MIN_NOTE_DEPOSIT, ContractError::MinAmount);
+
+                let note = PostedNote::new(
+                    note.title,
+                    note.body,
+                    None,
+                    sender_id.clone(),
+                    note.tags.unwrap_or_default(),
                 );
 
-                let next_entry_id = self.next_entry_id.unwrap_or(0);
+                // Storage is paid from the storage balance of the sender
+                let id = self.internal_add_note(sender_id.clone(), note, 0);
 
-                let note = PostedNote {
-                    id: Some(note.id.unwrap_or(next_entry_id.into()).into()),
-                    ..note
-                };
+                // The deposit record and the points of the sender are charged
+                // on top of the note
+                let storage_usage = env::storage_usage();
 
-                self.internal_add_note(
-                    sender_id.clone(),
-                    &note,
-                    None,
-                    next_entry_id,
+                self.note_deposits.insert(
+                    (sender_id.clone(), id),
+                    NoteDeposit {
+                        token_id: env::predecessor_account_id(),
+                        amount,
+                    },
                 );
 
-                self.user_points
-                    .entry(sender_id)
-                    .and_modify(|points| points.0 += amount.0)
-                    .or_insert(amount.0.into());
+                self.internal_update_points(sender_id.clone(), |points| {
+                    points.unwrap_or(0) + amount.0
+                });
+
+                self.note_deposits.flush();
+                self.user_points.flush();
+
+                self.internal_settle_storage(&sender_id, storage_usage);
             }
 
-            FtMessage::RemoveNote(note) => {
-                todo!("Remove note")
+            FtMessage::RemoveNote(id) => {
+                self.internal_remove_note(sender_id, id.0);
+
+                // Nothing is spent on removal, so the transfer is refunded
+                return PromiseOrValue::Value(amount);
             }
 
             FtMessage::Exchange => {
+                let rate = self.exchange_rate;
+
                 let (token_in, token_out, amount_out) = if token == Token::Near
                 {
-                    require!(
+                    ensure!(
                         amount.0 >= NearToken::from_near(1).as_yoctonear(),
-                        "ERR_MIN_AMOUNT"
+                        ContractError::MinAmount
                     );
-                    let amount_out = amount.0 * 4;
+                    let amount_out =
+                        amount.0.checked_mul(rate).unwrap_or_else(|| {
+                            ContractError::AmountTooLarge.panic()
+                        });
 
                     (Token::Near, Token::FungibleToken, amount_out)
                 } else {
-                    require!(
-                        amount.0 >= NearToken::from_near(4).as_yoctonear(),
-                        "ERR_MIN_AMOUNT"
+                    // At least one Near is given out
+                    ensure!(
+                        amount.0
+                            >= NearToken::from_near(1)
+                                .as_yoctonear()
+                                .saturating_mul(rate),
+                        ContractError::MinAmount
                     );
 
-                    let amount_out = amount.0 / 4;
+                    let amount_out = amount.0 / rate;
 
                     (Token::FungibleToken, Token::Near, amount_out)
                 };
@@ -151,3 +218,195 @@ impl FungibleTokenReceiver for Contract {
         PromiseOrValue::Value(0.into())
     }
 }
+
+#[near]
+impl Contract {
+    pub(crate) fn internal_refund_note_deposit(
+        &mut self,
+        account_id: AccountId,
+        deposit: NoteDeposit,
+    ) -> Promise {
+        self.internal_update_points(account_id.clone(), |points| {
+            points.unwrap_or(0).saturating_sub(deposit.amount.0)
+        });
+
+        ext_ft::ext(deposit.token_id)
+            .with_attached_deposit(NearToken::from_yoctonear(1))
+            .ft_transfer(account_id.clone(), deposit.amount)
+            .then(
+                Self::ext(env::current_account_id())
+                    .resolve_note_deposit_refund(account_id, deposit.amount),
+            )
+    }
+
+    #[private]
+    pub fn resolve_note_deposit_refund(
+        &mut self,
+        account_id: AccountId,
+        amount: U128,
+    ) -> U128 {
+        if is_promise_success() {
+            log!("Note deposit refunded");
+            amount
+        } else {
+            log!("Note deposit refund failed");
+
+            self.internal_update_points(account_id, |points| {
+                points.unwrap_or(0) + amount.0
+            });
+
+            0.into()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use near_contract_standards::storage_management::StorageManagement;
+
+    use super::*;
+    use crate::{
+        ownership::Role,
+        test_utils::{
+            execute, new_contract, new_contract_without_timelock, set_context,
+        },
+        timelock::Action,
+    };
+
+    #[test]
+    fn add_note_charges_storage() {
+        let mut contract = new_contract();
+
+        set_context("account_id1", NearToken::from_near(1));
+        contract.storage_deposit(None, None);
+
+        // tokens are told apart through the stored state
+        env::state_write(&contract);
+
+        let account_id: AccountId = "account_id1".parse().unwrap();
+
+        let available = contract
+            .storage_balance_of(account_id.clone())
+            .unwrap()
+            .available;
+
+        set_context("some_acc.near", NearToken::from_near(0));
+        let storage_usage = env::storage_usage();
+
+        contract.ft_on_transfer(
+            account_id.clone(),
+            U128(MIN_NOTE_DEPOSIT),
+            r#"{"AddNote":{"title":"title","body":"body"}}"#.to_string(),
+        );
+
+        // the note, its deposit and the first points of the sender are all
+        // paid for by the sender
+        let used = (env::storage_usage() - storage_usage) as u128;
+        let charged = available.as_yoctonear()
+            - contract
+                .storage_balance_of(account_id.clone())
+                .unwrap()
+                .available
+                .as_yoctonear();
+
+        assert!(contract.note_deposits.contains_key(&(account_id, 0)));
+        assert_eq!(charged, used * env::storage_byte_cost().as_yoctonear());
+    }
+
+    #[test]
+    fn remove_note() {
+        let mut contract = new_contract();
+
+        set_context("account_id1", NearToken::from_near(1));
+        contract.storage_deposit(None, None);
+
+        env::state_write(&contract);
+
+        let account_id: AccountId = "account_id1".parse().unwrap();
+
+        set_context("some_acc.near", NearToken::from_near(0));
+        contract.ft_on_transfer(
+            account_id.clone(),
+            U128(MIN_NOTE_DEPOSIT),
+            r#"{"AddNote":{"title":"title","body":"body"}}"#.to_string(),
+        );
+
+        let refund = contract.ft_on_transfer(
+            account_id.clone(),
+            U128(1),
+            r#"{"RemoveNote":"0"}"#.to_string(),
+        );
+
+        // nothing is spent on removal
+        assert!(matches!(refund, PromiseOrValue::Value(U128(1))));
+        assert!(contract.get_notes(account_id, None, None).is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_INVALID_MESSAGE")]
+    fn remove_note_numeric_id() {
+        let mut contract = new_contract();
+
+        env::state_write(&contract);
+
+        set_context("some_acc.near", NearToken::from_near(0));
+        contract.ft_on_transfer(
+            "account_id1".parse().unwrap(),
+            U128(1),
+            r#"{"RemoveNote":0}"#.to_string(),
+        );
+    }
+
+    #[test]
+    fn exchange_at_rate() {
+        let mut contract = new_contract_without_timelock();
+
+        set_context("manager.near", NearToken::from_near(0));
+        execute(
+            &mut contract,
+            Action::GrantRole {
+                account_id: "rate_setter.near".parse().unwrap(),
+                role: Role::RateSetter,
+            },
+        );
+
+        set_context("rate_setter.near", NearToken::from_near(0));
+        let id = contract.set_exchange_rate(U128(5));
+        contract.execute_action(id);
+
+        assert_eq!(contract.get_exchange_rate().0, 5);
+
+        env::state_write(&contract);
+
+        // "some_acc.near" stands for wrapped Near
+        set_context("some_acc.near", NearToken::from_near(0));
+        contract.ft_on_transfer(
+            "account_id1".parse().unwrap(),
+            U128(NearToken::from_near(1).as_yoctonear()),
+            r#""Exchange""#.to_string(),
+        );
+
+        assert_eq!(
+            contract.get_user_points("account_id1".parse().unwrap()).0,
+            NearToken::from_near(5).as_yoctonear()
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_MISSING_ROLE")]
+    fn set_exchange_rate_not_rate_setter() {
+        let mut contract = new_contract_without_timelock();
+
+        set_context("manager.near", NearToken::from_near(0));
+        execute(
+            &mut contract,
+            Action::GrantRole {
+                account_id: "pauser.near".parse().unwrap(),
+                role: Role::Pauser,
+            },
+        );
+
+        set_context("pauser.near", NearToken::from_near(0));
+        contract.set_exchange_rate(U128(5));
+    }
+}
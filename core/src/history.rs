@@ -0,0 +1,228 @@
+use near_sdk::{
+    borsh::BorshSerialize, env, json_types::U64, near, store::LookupMap,
+    AccountId, FunctionError,
+};
+
+use crate::{
+    content::{ContentRef, NoteContent},
+    encryption::EncryptedBody,
+    errors::ContractError,
+    pausable::{Feature, Pausable},
+    Contract, ContractExt,
+};
+
+// Revisions kept per note, the oldest ones are dropped first
+pub(crate) const MAX_REVISIONS: usize = 10;
+
+// The content of a note before an edit, with the account that made the edit
+// and when
+#[near(serializers = [borsh, json])]
+#[derive(Clone)]
+pub struct NoteRevision {
+    pub title: String,
+    pub body: String,
+    pub encrypted: Option<EncryptedBody>,
+    pub content_ref: Option<ContentRef>,
+    pub editor: AccountId,
+    pub timestamp: U64,
+}
+
+#[near]
+impl Contract {
+    // Previous revisions of a note, oldest first
+    pub fn get_note_history(
+        &self,
+        account_id: AccountId,
+        id: U64,
+    ) -> Vec<&NoteRevision> {
+        self.note_revisions
+            .get(&(account_id, id.0))
+            .map_or(vec![], |revisions| revisions.iter().collect())
+    }
+
+    // Only the author can restore a revision. Restoring is an edit itself, so
+    // the current content is kept as a new revision
+    #[payable]
+    pub fn restore_note_revision(&mut self, id: U64, revision: u32) {
+        self.assert_feature_not_paused(Feature::Notes);
+        self.assert_not_migrating();
+
+        let account_id = env::predecessor_account_id();
+
+        let NoteRevision {
+            title,
+            body,
+            encrypted,
+            content_ref,
+            ..
+        } = self
+            .note_revisions
+            .get(&(account_id.clone(), id.0))
+            .and_then(|revisions| revisions.get(revision as usize))
+            .cloned()
+            .unwrap_or_else(|| ContractError::RevisionNotFound.panic());
+
+        let deposit = env::attached_deposit().as_yoctonear();
+
+        if deposit != 0 {
+            self.internal_storage_deposit(&account_id, deposit);
+        }
+
+        let content = NoteContent {
+            body,
+            encrypted,
+            content_ref,
+        };
+
+        self.internal_update_note(account_id, id, title, content, None);
+    }
+
+    pub(crate) fn internal_push_revision(
+        &mut self,
+        account_id: &AccountId,
+        id: u64,
+        revision: NoteRevision,
+    ) {
+        push_revision(
+            &mut self.note_revisions,
+            (account_id.clone(), id),
+            revision,
+        );
+    }
+
+    pub(crate) fn internal_push_workspace_revision(
+        &mut self,
+        workspace_id: u64,
+        id: u64,
+        revision: NoteRevision,
+    ) {
+        push_revision(
+            &mut self.workspace_note_revisions,
+            (workspace_id, id),
+            revision,
+        );
+    }
+}
+
+// Appends `revision` to the revisions kept under `key`, personal and workspace
+// notes each having their own
+fn push_revision<K>(
+    note_revisions: &mut LookupMap<K, Vec<NoteRevision>>,
+    key: K,
+    revision: NoteRevision,
+) where
+    K: BorshSerialize + Ord + Clone,
+{
+    if let Some(revisions) = note_revisions.get_mut(&key) {
+        if revisions.len() == MAX_REVISIONS {
+            revisions.remove(0);
+        }
+
+        revisions.push(revision);
+    } else {
+        note_revisions.insert(key, vec![revision]);
+    }
+
+    note_revisions.flush();
+}
+
+#[cfg(test)]
+mod tests {
+    use near_sdk::NearToken;
+
+    use super::*;
+    use crate::test_utils::{new_contract, set_context_at};
+
+    #[test]
+    fn get_note_history() {
+        let mut contract = new_contract();
+
+        set_context_at("account_id1", NearToken::from_near(1), 0);
+        contract.add_note("title".into(), "body".into(), None);
+
+        set_context_at("account_id1", NearToken::from_near(1), 10);
+        contract.update_note(
+            U64(0),
+            "title2".into(),
+            "body2".into(),
+            None,
+            None,
+        );
+
+        let history =
+            contract.get_note_history("account_id1".parse().unwrap(), U64(0));
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].title, "title");
+        assert_eq!(history[0].editor.as_str(), "account_id1");
+        assert_eq!(history[0].timestamp, U64(10));
+    }
+
+    #[test]
+    fn restore_note_revision() {
+        let mut contract = new_contract();
+
+        set_context_at("account_id1", NearToken::from_near(1), 0);
+        contract.add_note("title".into(), "body".into(), None);
+        contract.update_note(
+            U64(0),
+            "title2".into(),
+            "body2".into(),
+            None,
+            None,
+        );
+
+        contract.restore_note_revision(U64(0), 0);
+
+        let account_id: AccountId = "account_id1".parse().unwrap();
+
+        let note = contract.get_note(account_id.clone(), U64(0));
+        assert_eq!(note.title, "title");
+        assert_eq!(note.body, "body");
+
+        let history = contract.get_note_history(account_id, U64(0));
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[1].title, "title2");
+    }
+
+    #[test]
+    fn history_is_bounded() {
+        let mut contract = new_contract();
+
+        set_context_at("account_id1", NearToken::from_near(1), 0);
+        contract.add_note("0".into(), "body".into(), None);
+
+        for i in 1..=MAX_REVISIONS + 1 {
+            contract.update_note(
+                U64(0),
+                i.to_string(),
+                "body".into(),
+                None,
+                None,
+            );
+        }
+
+        let history =
+            contract.get_note_history("account_id1".parse().unwrap(), U64(0));
+        assert_eq!(history.len(), MAX_REVISIONS);
+        assert_eq!(history[0].title, "1");
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_REVISION_NOT_FOUND")]
+    fn restore_revision_of_other_account() {
+        let mut contract = new_contract();
+
+        set_context_at("account_id1", NearToken::from_near(1), 0);
+        contract.add_note("title".into(), "body".into(), None);
+        contract.update_note(
+            U64(0),
+            "title2".into(),
+            "body2".into(),
+            None,
+            None,
+        );
+
+        set_context_at("account_id2", NearToken::from_near(1), 0);
+        contract.restore_note_revision(U64(0), 0);
+    }
+}
@@ -1,28 +1,84 @@
+mod attachments;
+mod content;
+#[cfg(any(test, feature = "crypto"))]
+pub mod crypto;
+mod encryption;
+mod errors;
+pub mod events;
 mod exchange;
 mod ft_receiver;
+mod history;
+mod limits;
 mod migration;
 mod ownership;
 mod pausable;
+mod sharing;
+mod storage;
+mod tags;
+#[cfg(test)]
+mod test_utils;
+mod timelock;
+mod upgrade;
+mod workspace;
+
+pub use attachments::Attachment;
+pub use content::ContentRef;
+pub use encryption::{EncryptedBody, EncryptionScheme, KeyEnvelope};
+pub use errors::ContractError;
+pub use limits::NoteLimits;
+pub use ownership::Role;
+pub use pausable::Feature;
+pub use sharing::Permission;
+pub use timelock::Action;
+pub use workspace::WorkspaceRole;
 
 use std::u32;
 
 use near_sdk::{
     collections::{LookupMap as LookUpMapCollections, UnorderedSet},
     env,
-    json_types::{U128, U64},
-    log, near, require,
-    store::{IterableMap, IterableSet, LookupMap, LookupSet},
-    AccountId, BorshStorageKey, NearToken, PanicOnDefault, Promise,
+    json_types::{Base64VecU8, U128, U64},
+    log, near,
+    store::{IterableMap, IterableSet, LookupMap, Vector},
+    AccountId, BorshStorageKey, CryptoHash, FunctionError, NearToken,
+    PanicOnDefault,
+};
+
+use crate::{
+    content::NoteContent,
+    errors::{ensure, ContractError},
+    events::{
+        NoteAdded, NoteRemoved, NoteUpdated, NotebookEvent, PointsChanged,
+    },
+    exchange::DEFAULT_EXCHANGE_RATE,
+    ft_receiver::NoteDeposit,
+    history::NoteRevision,
+    migration::{write_state_version, NoteMigration},
+    pausable::Pausable,
+    storage::StorageAccount,
+    tags::{assert_valid_tags, diff_tags},
+    timelock::{QueuedAction, DEFAULT_TIMELOCK_DELAY},
+    upgrade::{UpgradeProposal, UpgradeRecord, DEFAULT_UPGRADE_TTL},
+    workspace::Workspace,
 };
 
 const MIN_NOTE_DEPOSIT: u128 = NearToken::from_near(1).as_yoctonear();
 #[near(serializers = [borsh, json])]
-#[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Clone, PartialEq, Eq)]
 pub struct PostedNote {
     pub id: Option<U64>,
     pub title: String,
     pub body: String,
-    //pub author: AccountId, // MIGRATION HOMEWORK: UNCOMMENT
+    pub author: AccountId,
+    pub tags: Vec<String>,
+    // Set instead of `body` for encrypted notes
+    pub encrypted: Option<EncryptedBody>,
+    // Set instead of `body` for notes stored off chain
+    pub content_ref: Option<ContentRef>,
+    pub attachments: Vec<Attachment>,
+    // Block timestamps in nanoseconds
+    pub created_at: U64,
+    pub updated_at: U64,
 }
 
 impl PostedNote {
@@ -30,13 +86,22 @@ impl PostedNote {
         title: String,
         body: String,
         id: Option<U64>,
-        // author: AccountId,
+        author: AccountId,
+        tags: Vec<String>,
     ) -> Self {
+        let now = U64(env::block_timestamp());
+
         Self {
             title,
             body,
             id,
-            // author,
+            author,
+            tags,
+            encrypted: None,
+            content_ref: None,
+            attachments: vec![],
+            created_at: now,
+            updated_at: now,
         }
     }
 }
@@ -48,6 +113,41 @@ pub enum StorageKey {
     Notes(AccountId),
     Managers,
     UserPoints,
+    NoteIdsPerUser,
+    NoteIds(AccountId),
+    NotesById,
+    NoteDeposits,
+    StorageAccounts,
+    PausedFeatures,
+    Roles,
+    RoleMembers(Role),
+    StagedCode,
+    UpgradeProposals,
+    QueuedActions,
+    UpgradeHistory,
+    AccountTags,
+    Tags(AccountId),
+    TaggedNotes,
+    TaggedNoteIds(AccountId, String),
+    SharedWith,
+    SharedNotes(AccountId),
+    Grantees,
+    GranteeIds(AccountId, Option<u64>),
+    Workspaces,
+    WorkspaceMembers(u64),
+    WorkspaceInvites(u64),
+    WorkspaceNoteIds(u64),
+    WorkspaceNotes,
+    NoteRevisions,
+    NoteKeys,
+    NoteKeyEnvelopes(AccountId, u64),
+    EncryptionKeys,
+    NoteOwners,
+    WorkspaceNoteRevisions,
+    WorkspaceTagSets,
+    WorkspaceTags(u64),
+    TaggedWorkspaceNotes,
+    WorkspaceTaggedNoteIds(u64, String),
 }
 
 // Define the contract structure
@@ -55,15 +155,67 @@ pub enum StorageKey {
 #[derive(PanicOnDefault)]
 pub struct Contract {
     greeting: String,
-    note_book: IterableMap<AccountId, IterableSet<PostedNote>>,
+    // Ids of the notes posted by each account, used to enumerate a note book
+    note_book: IterableMap<AccountId, IterableSet<u64>>,
+    // Index over every posted note keyed by its author and id
+    notes: LookupMap<(AccountId, u64), PostedNote>,
+    // Author of each posted note, so that notes can be looked up by id alone
+    note_owners: LookupMap<u64, AccountId>,
+    // Tokens deposited through `ft_on_transfer` to pay for a note
+    note_deposits: LookupMap<(AccountId, u64), NoteDeposit>,
+    // Tags used by each account, and the ids of its notes under each tag
+    account_tags: LookupMap<AccountId, IterableSet<String>>,
+    tagged_notes: LookupMap<(AccountId, String), IterableSet<u64>>,
+    // Notes and note books shared with each account, keyed by the author and
+    // the note id, `None` standing for the whole note book
+    shared_with:
+        LookupMap<AccountId, IterableMap<(AccountId, Option<u64>), Permission>>,
+    // Accounts each note or note book is shared with
+    grantees: LookupMap<(AccountId, Option<u64>), IterableSet<AccountId>>,
+    workspaces: LookupMap<u64, Workspace>,
+    // Notes of each workspace keyed by the workspace and note id
+    workspace_notes: LookupMap<(u64, u64), PostedNote>,
+    // Tag index of the notes of each workspace, like the one of accounts
+    workspace_tags: LookupMap<u64, IterableSet<String>>,
+    tagged_workspace_notes: LookupMap<(u64, String), IterableSet<u64>>,
+    next_workspace_id: u64,
+    // Previous revisions of each note, oldest first
+    note_revisions: LookupMap<(AccountId, u64), Vec<NoteRevision>>,
+    workspace_note_revisions: LookupMap<(u64, u64), Vec<NoteRevision>>,
+    // Content keys of encrypted notes, wrapped for each recipient
+    note_keys: LookupMap<(AccountId, u64), IterableMap<AccountId, KeyEnvelope>>,
+    // X25519 public keys content keys are wrapped with
+    encryption_keys: LookupMap<AccountId, Base64VecU8>,
+    note_limits: NoteLimits,
     // note_book_collections:
     //     LookUpMapCollections<AccountId, UnorderedSet<PostedNote>>,
     pub ft_id: AccountId,
     pub w_near_id: AccountId,
+    // FungibleToken given by the exchange for one Near
+    exchange_rate: u128,
     next_entry_id: Option<u64>,
-    managers: LookupSet<AccountId>,
+    // Accounts holding each privileged role
+    roles: IterableMap<Role, IterableSet<AccountId>>,
     user_points: LookupMap<AccountId, U128>,
-    version: U64,
+    // NEP-145 storage balances that pay for the notes of each account
+    storage_accounts: LookupMap<AccountId, StorageAccount>,
+    // Storage taken by a single storage balance, the minimum deposit
+    account_storage_usage: u64,
+    paused: bool,
+    paused_features: IterableSet<Feature>,
+    // Code waiting for approvals before an upgrade, keyed by its sha256
+    staged_code: LookupMap<CryptoHash, Vec<u8>>,
+    upgrade_proposals: LookupMap<CryptoHash, UpgradeProposal>,
+    // Approvals needed to deploy staged code
+    upgrade_threshold: u32,
+    upgrade_ttl: u64,
+    upgrade_history: Vector<UpgradeRecord>,
+    // Privileged actions waiting out the timelock delay
+    queued_actions: IterableMap<u64, QueuedAction>,
+    next_action_id: u64,
+    timelock_delay: u64,
+    // Set while notes of a previous layout are being migrated in batches
+    note_migration: Option<NoteMigration>,
 }
 
 // Implement the contract structure
@@ -74,24 +226,71 @@ impl Contract {
         ft_id: AccountId,
         w_near_id: AccountId,
         managers: Vec<AccountId>,
+        timelock_delay: Option<U64>,
     ) -> Self {
-        let mut managers_set = LookupSet::new(StorageKey::Managers);
+        ensure!(!managers.is_empty(), ContractError::NoManagers);
+
+        // The initial managers become the admins of the contract
+        let mut admins = IterableSet::new(StorageKey::RoleMembers(Role::Admin));
 
         managers.into_iter().for_each(|manager| {
-            managers_set.insert(manager);
+            admins.insert(manager);
         });
 
-        Self {
+        let mut roles = IterableMap::new(StorageKey::Roles);
+        roles.insert(Role::Admin, admins);
+
+        let mut this = Self {
             greeting: "Hello".to_string(),
-            note_book: IterableMap::new(StorageKey::NotesPerUser),
+            note_book: IterableMap::new(StorageKey::NoteIdsPerUser),
+            notes: LookupMap::new(StorageKey::NotesById),
+            note_owners: LookupMap::new(StorageKey::NoteOwners),
+            note_deposits: LookupMap::new(StorageKey::NoteDeposits),
+            account_tags: LookupMap::new(StorageKey::AccountTags),
+            tagged_notes: LookupMap::new(StorageKey::TaggedNotes),
+            shared_with: LookupMap::new(StorageKey::SharedWith),
+            grantees: LookupMap::new(StorageKey::Grantees),
+            workspaces: LookupMap::new(StorageKey::Workspaces),
+            workspace_notes: LookupMap::new(StorageKey::WorkspaceNotes),
+            workspace_tags: LookupMap::new(StorageKey::WorkspaceTagSets),
+            tagged_workspace_notes: LookupMap::new(
+                StorageKey::TaggedWorkspaceNotes,
+            ),
+            next_workspace_id: 0,
+            note_revisions: LookupMap::new(StorageKey::NoteRevisions),
+            workspace_note_revisions: LookupMap::new(
+                StorageKey::WorkspaceNoteRevisions,
+            ),
+            note_keys: LookupMap::new(StorageKey::NoteKeys),
+            encryption_keys: LookupMap::new(StorageKey::EncryptionKeys),
+            note_limits: NoteLimits::default(),
             //note_book_collections: LookUpMapCollections::new(b"mm".to_vec()),
             ft_id,
             w_near_id,
-            managers: managers_set,
+            exchange_rate: DEFAULT_EXCHANGE_RATE,
+            roles,
             next_entry_id: None,
             user_points: LookupMap::new(StorageKey::UserPoints),
-            version: U64(1),
-        }
+            storage_accounts: LookupMap::new(StorageKey::StorageAccounts),
+            account_storage_usage: 0,
+            paused: false,
+            paused_features: IterableSet::new(StorageKey::PausedFeatures),
+            staged_code: LookupMap::new(StorageKey::StagedCode),
+            upgrade_proposals: LookupMap::new(StorageKey::UpgradeProposals),
+            upgrade_threshold: 1,
+            upgrade_ttl: DEFAULT_UPGRADE_TTL,
+            upgrade_history: Vector::new(StorageKey::UpgradeHistory),
+            queued_actions: IterableMap::new(StorageKey::QueuedActions),
+            next_action_id: 0,
+            timelock_delay: timelock_delay
+                .map_or(DEFAULT_TIMELOCK_DELAY, |d| d.0),
+            note_migration: None,
+        };
+
+        this.measure_account_storage_usage();
+        write_state_version();
+
+        this
     }
 
     // Public method - returns the greeting saved, defaulting to
@@ -101,34 +300,37 @@ impl Contract {
 
     // Public method - accepts a greeting, such as "howdy", and records it
     pub fn set_greeting(&mut self, greeting: String) {
+        self.assert_feature_not_paused(Feature::Greetings);
+
         log!("Saving greeting: {greeting}");
         self.greeting = greeting;
     }
 
-    pub fn get_version(&self) -> U64 {
-        self.version.clone()
-    }
-
-    // MIGRATION HOMEWORK: Modify TO ALSO PASS AUTHOR TO NOTE author for
-    // Migration homework
+    // The attached deposit is added to the storage balance of the caller,
+    // which pays for the note
     #[payable]
-    pub fn add_note(&mut self, title: String, body: String) {
-        let account_id = env::predecessor_account_id();
+    pub fn add_note(
+        &mut self,
+        title: String,
+        body: String,
+        tags: Option<Vec<String>>,
+    ) {
+        self.assert_feature_not_paused(Feature::Notes);
 
-        let next_entry_id = self.next_entry_id.unwrap_or(0);
+        let account_id = env::predecessor_account_id();
 
         let note = PostedNote::new(
-            title.clone(),
+            title,
             body,
-            Some(next_entry_id.into()),
-            // account_id.clone(),
+            None,
+            account_id.clone(),
+            tags.unwrap_or_default(),
         );
 
         self.internal_add_note(
-            account_id.clone(),
-            &note,
-            Some(env::attached_deposit().as_yoctonear()),
-            next_entry_id,
+            account_id,
+            note,
+            env::attached_deposit().as_yoctonear(),
         );
     }
 
@@ -146,20 +348,85 @@ impl Contract {
     //     log!("Added note to the note book: {}", note.title);
     // }
 
+    // The author, or an account they gave write access to, can edit a note.
+    // `author` defaults to the caller. The id stays the same and the storage
+    // difference is charged to or credited back to the storage balance of the
+    // author. Tags are left as they are unless new ones are given
+    #[payable]
+    pub fn update_note(
+        &mut self,
+        id: U64,
+        title: String,
+        body: String,
+        tags: Option<Vec<String>>,
+        author: Option<AccountId>,
+    ) {
+        self.assert_feature_not_paused(Feature::Notes);
+        self.assert_not_migrating();
+
+        if let Some(tags) = &tags {
+            assert_valid_tags(tags);
+        }
+
+        let account_id = author.unwrap_or_else(env::predecessor_account_id);
+
+        self.assert_can_write(&account_id, id.0);
+
+        let deposit = env::attached_deposit().as_yoctonear();
+
+        if deposit != 0 {
+            self.internal_storage_deposit(&account_id, deposit);
+        }
+
+        self.internal_update_note(
+            account_id,
+            id,
+            title,
+            NoteContent::plain(body),
+            tags,
+        );
+    }
+
+    // The author, an account they gave write access to, or a moderator can
+    // remove a note. The freed storage is credited back to the storage balance
+    // of the author
+    pub fn remove_note(&mut self, id: U64, author: Option<AccountId>) {
+        self.assert_feature_not_paused(Feature::Notes);
+
+        let account_id = author.unwrap_or_else(env::predecessor_account_id);
+
+        if !self.has_role(env::predecessor_account_id(), Role::Moderator) {
+            self.assert_can_write(&account_id, id.0);
+        }
+
+        self.internal_remove_note(account_id, id.0);
+    }
+
     pub fn get_note(&self, account_id: AccountId, id: U64) -> &PostedNote {
         let id = id.0;
 
-        require!(id <= self.next_entry_id.unwrap_or(0), "Note does not exist");
+        ensure!(
+            id < self.next_entry_id.unwrap_or(0),
+            ContractError::NoteNotFound
+        );
 
-        let notes = self
-            .note_book
-            .get(&account_id)
-            .unwrap_or_else(|| env::panic_str("no entry"));
+        self.notes
+            .get(&(account_id, id))
+            .unwrap_or_else(|| ContractError::NoteNotFound.panic())
+    }
 
-        notes
-            .iter()
-            .find(|note| note.id.unwrap() == id.into())
-            .unwrap_or_else(|| env::panic_str("no entry"))
+    // Ids are unique across accounts, so a note can be looked up without
+    // knowing its author. Only personal notes are indexed, the notes of a
+    // workspace are read with `get_workspace_note`
+    pub fn get_note_by_id(&self, id: U64) -> &PostedNote {
+        let account_id = self
+            .note_owners
+            .get(&id.0)
+            .unwrap_or_else(|| ContractError::NoteNotFound.panic());
+
+        self.notes
+            .get(&(account_id.clone(), id.0))
+            .unwrap_or_else(|| ContractError::NoteNotFound.panic())
     }
 
     pub fn get_notes(
@@ -168,59 +435,258 @@ impl Contract {
         from_index: Option<u32>,
         limit: Option<u32>,
     ) -> Vec<&PostedNote> {
-        let notes = self
+        let ids = self
             .note_book
             .get(&account_id)
-            .unwrap_or_else(|| env::panic_str("no entry"));
+            .unwrap_or_else(|| ContractError::NoteBookNotFound.panic());
 
-        notes
-            .iter()
+        ids.iter()
             .skip(from_index.unwrap_or(0) as usize)
             .take(limit.unwrap_or(u32::MAX) as usize)
+            .map(|id| {
+                self.notes
+                    .get(&(account_id.clone(), *id))
+                    .unwrap_or_else(|| ContractError::NoteNotFound.panic())
+            })
             .collect()
     }
 
     pub fn get_user_points(&self, account_id: AccountId) -> &U128 {
-        self.user_points.get(&account_id).expect("no entry")
+        self.user_points
+            .get(&account_id)
+            .unwrap_or_else(|| ContractError::NoPoints.panic())
     }
 
-    fn internal_add_note(
+    // Applies `update` to the current points of `account_id`, if it has any,
+    // and stores the result
+    pub(crate) fn internal_update_points(
         &mut self,
         account_id: AccountId,
-        note: &PostedNote,
-        deposit: Option<u128>,
-        next_entry_id: u64,
+        update: impl FnOnce(Option<u128>) -> u128,
     ) {
+        let points =
+            update(self.user_points.get(&account_id).map(|points| points.0));
+
+        self.user_points.insert(account_id.clone(), points.into());
+
+        NotebookEvent::PointsChanged(PointsChanged {
+            account_id,
+            points: points.into(),
+        })
+        .emit();
+    }
+
+    // Ids come from a single counter shared by the notes of every account and
+    // workspace, and are never reused. The migration of legacy notes moves it
+    // past every legacy id first, so the id it gives out is always free
+    pub(crate) fn internal_next_note_id(&mut self) -> u64 {
+        let id = self.next_entry_id.unwrap_or(0);
+
+        self.next_entry_id = Some(id + 1);
+
+        id
+    }
+
+    // Stores a new note of `account_id` under the next id, which is returned
+    fn internal_add_note(
+        &mut self,
+        account_id: AccountId,
+        mut note: PostedNote,
+        deposit: u128,
+    ) -> u64 {
+        self.assert_not_migrating();
+
+        if deposit != 0 {
+            self.internal_storage_deposit(&account_id, deposit);
+        }
+
         let storage_usage = env::storage_usage();
 
-        if let Some(notes) = self.note_book.get_mut(&account_id) {
-            notes.insert(note.clone());
+        assert_valid_tags(&note.tags);
+        self.note_limits.assert_valid_note(
+            &note.title,
+            &note.body,
+            note.encrypted.as_ref(),
+        );
+
+        let id = self.internal_next_note_id();
+        note.id = Some(U64(id));
+
+        if let Some(ids) = self.note_book.get_mut(&account_id) {
+            ids.insert(id);
         } else {
-            self.note_book.insert(
-                account_id.clone(),
-                IterableSet::new(StorageKey::Notes(account_id.clone())),
-            );
+            let mut ids =
+                IterableSet::new(StorageKey::NoteIds(account_id.clone()));
+            ids.insert(id);
 
-            let notes = self.note_book.get_mut(&account_id).unwrap();
-            notes.insert(note.clone());
+            self.note_book.insert(account_id.clone(), ids);
         }
 
-        self.next_entry_id = Some(next_entry_id + 1);
+        self.internal_tag_note(&account_id, id, &note.tags);
+
+        NotebookEvent::NoteAdded(NoteAdded {
+            account_id: account_id.clone(),
+            id: id.into(),
+            title: note.title.clone(),
+            workspace_id: None,
+        })
+        .emit();
 
-        let storage_cost = env::storage_byte_cost().as_yoctonear()
-            * (env::storage_usage() - storage_usage) as u128;
+        self.notes.insert((account_id.clone(), id), note);
+        self.note_owners.insert(id, account_id.clone());
 
-        let to_refund = deposit
-            .unwrap_or(0)
-            .checked_sub(storage_cost)
-            .expect("not enough attached deposit");
+        self.flush_note_book(&account_id);
+
+        self.internal_settle_storage(&account_id, storage_usage);
+
+        id
+    }
+
+    // Edits a note of `account_id`, keeping its previous content as a
+    // revision. Storage is settled against the author. A note keeps its kind,
+    // encrypted notes stay encrypted and references stay references
+    pub(crate) fn internal_update_note(
+        &mut self,
+        account_id: AccountId,
+        id: U64,
+        title: String,
+        content: NoteContent,
+        tags: Option<Vec<String>>,
+    ) {
+        self.note_limits.assert_valid_note(
+            &title,
+            &content.body,
+            content.encrypted.as_ref(),
+        );
+
+        let storage_usage = env::storage_usage();
+
+        let note = self
+            .notes
+            .get_mut(&(account_id.clone(), id.0))
+            .unwrap_or_else(|| ContractError::NoteNotFound.panic());
+
+        // Encrypted bodies stay bound to the context the note was created
+        // with
+        ensure!(
+            note.encrypted.as_ref().map(|encrypted| &encrypted.context)
+                == content
+                    .encrypted
+                    .as_ref()
+                    .map(|encrypted| &encrypted.context),
+            ContractError::EncryptionMismatch
+        );
+        ensure!(
+            note.content_ref.is_some() == content.content_ref.is_some(),
+            ContractError::ContentRefMismatch
+        );
+
+        let revision = NoteRevision {
+            title: std::mem::replace(&mut note.title, title),
+            body: std::mem::replace(&mut note.body, content.body),
+            encrypted: std::mem::replace(
+                &mut note.encrypted,
+                content.encrypted,
+            ),
+            content_ref: std::mem::replace(
+                &mut note.content_ref,
+                content.content_ref,
+            ),
+            editor: env::predecessor_account_id(),
+            timestamp: U64(env::block_timestamp()),
+        };
+
+        note.updated_at = U64(env::block_timestamp());
+
+        let old_tags = tags.map(|tags| std::mem::replace(&mut note.tags, tags));
+        let new_tags = note.tags.clone();
+
+        NotebookEvent::NoteUpdated(NoteUpdated {
+            account_id: account_id.clone(),
+            id,
+            title: note.title.clone(),
+            workspace_id: None,
+        })
+        .emit();
+
+        if let Some(old_tags) = old_tags {
+            let (removed, added) = diff_tags(old_tags, &new_tags);
+
+            self.internal_untag_note(&account_id, id.0, &removed);
+            self.internal_tag_note(&account_id, id.0, &added);
+        }
+
+        self.internal_push_revision(&account_id, id.0, revision);
+
+        self.notes.flush();
+
+        self.internal_settle_storage(&account_id, storage_usage);
+    }
+
+    fn internal_remove_note(&mut self, account_id: AccountId, id: u64) {
+        self.assert_not_migrating();
+
+        let storage_usage = env::storage_usage();
+
+        let note = self
+            .notes
+            .remove(&(account_id.clone(), id))
+            .unwrap_or_else(|| ContractError::NoteNotFound.panic());
+
+        // A legacy id shared with a note of another account stays indexed
+        // under that account
+        if self.note_owners.get(&id) == Some(&account_id) {
+            self.note_owners.remove(&id);
+        }
+
+        self.internal_untag_note(&account_id, id, &note.tags);
+        self.note_revisions.remove(&(account_id.clone(), id));
+        self.internal_remove_note_keys(&account_id, id);
+        self.internal_revoke_note_access(&account_id, id);
+
+        let ids = self
+            .note_book
+            .get_mut(&account_id)
+            .unwrap_or_else(|| ContractError::NoteBookNotFound.panic());
+
+        ids.remove(&id);
+
+        if ids.is_empty() {
+            self.note_book.remove(&account_id);
+        }
+
+        self.flush_note_book(&account_id);
+
+        NotebookEvent::NoteRemoved(NoteRemoved {
+            account_id: account_id.clone(),
+            id: id.into(),
+            workspace_id: None,
+        })
+        .emit();
+
+        // Tokens deposited with the note are given back as well, and the
+        // storage of their record is credited with the rest
+        let deposit = self.note_deposits.remove(&(account_id.clone(), id));
+        self.note_deposits.flush();
+
+        self.internal_settle_storage(&account_id, storage_usage);
+
+        if let Some(deposit) = deposit {
+            self.internal_refund_note_deposit(account_id, deposit);
+        }
+    }
 
-        if to_refund != 0 {
-            Promise::new(account_id)
-                .transfer(NearToken::from_yoctonear(to_refund));
+    // `store` collections cache their writes until they are flushed, so they
+    // have to be written out before `env::storage_usage` reflects them
+    fn flush_note_book(&mut self, account_id: &AccountId) {
+        if let Some(ids) = self.note_book.get_mut(account_id) {
+            ids.flush();
         }
 
-        log!("Added note to the note book: {}", note.title);
+        self.note_book.flush();
+        self.notes.flush();
+        self.note_owners.flush();
+        self.note_revisions.flush();
     }
 
     // fn internal_add_note_collection(
@@ -249,17 +715,21 @@ impl Contract {
  */
 #[cfg(test)]
 mod tests {
-    use near_sdk::{test_utils::VMContextBuilder, testing_env, NearToken};
+    use near_contract_standards::storage_management::StorageManagement;
+    use near_sdk::{test_utils::get_logs, NearToken};
 
     use super::*;
+    use crate::{
+        test_utils::{
+            execute, new_contract, new_contract_without_timelock, set_context,
+            set_context_at,
+        },
+        timelock::Action,
+    };
 
     #[test]
     fn get_default_greeting() {
-        let contract = Contract::new(
-            "some_acc.near".parse().unwrap(),
-            "some_acc.near".parse().unwrap(),
-            vec!["some_acc.near".parse().unwrap()],
-        );
+        let contract = new_contract();
         // this test did not call set_greeting so should return the default
         // "Hello" greeting
         assert_eq!(contract.get_greeting(), "Hello");
@@ -267,22 +737,14 @@ mod tests {
 
     #[test]
     fn set_then_get_greeting() {
-        let mut contract = Contract::new(
-            "some_acc.near".parse().unwrap(),
-            "some_acc.near".parse().unwrap(),
-            vec!["some_acc.near".parse().unwrap()],
-        );
+        let mut contract = new_contract();
         contract.set_greeting("howdy".to_string());
         assert_eq!(contract.get_greeting(), "howdy");
     }
 
     #[test]
     fn add_note() {
-        let mut contract = Contract::new(
-            "some_acc.near".parse().unwrap(),
-            "some_acc.near".parse().unwrap(),
-            vec!["some_acc.near".parse().unwrap()],
-        );
+        let mut contract = new_contract();
 
         let account_id = "account_id1";
         set_context(account_id, NearToken::from_near(1));
@@ -291,10 +753,15 @@ mod tests {
             "title".into(),
             "body".into(),
             Some(contract.next_entry_id.unwrap_or(0).into()),
-            // account_id.parse().unwrap(),
+            account_id.parse().unwrap(),
+            vec![],
         );
 
-        contract.add_note(posted_note.title.clone(), posted_note.body.clone());
+        contract.add_note(
+            posted_note.title.clone(),
+            posted_note.body.clone(),
+            None,
+        );
 
         let notes = contract
             .note_book
@@ -302,18 +769,22 @@ mod tests {
             .unwrap();
 
         assert_eq!(notes.len(), 1);
-        assert!(notes.contains(&posted_note));
+        assert!(notes.contains(&posted_note.id.unwrap().0));
 
         // add another note for the same account
         let posted_note_2 = PostedNote::new(
             "title2".into(),
             "body2".into(),
             Some(contract.next_entry_id.unwrap().into()),
-            // account_id.parse().unwrap(),
+            account_id.parse().unwrap(),
+            vec![],
         );
 
-        contract
-            .add_note(posted_note_2.title.clone(), posted_note_2.body.clone());
+        contract.add_note(
+            posted_note_2.title.clone(),
+            posted_note_2.body.clone(),
+            None,
+        );
 
         // add another note for a different account
         let account_id_2 = "account_id2";
@@ -323,11 +794,15 @@ mod tests {
             "title3".into(),
             "body3".into(),
             Some(contract.next_entry_id.unwrap().into()),
-            // account_id.parse().unwrap(),
+            account_id_2.parse().unwrap(),
+            vec![],
         );
 
-        contract
-            .add_note(posted_note_3.title.clone(), posted_note_3.body.clone());
+        contract.add_note(
+            posted_note_3.title.clone(),
+            posted_note_3.body.clone(),
+            None,
+        );
 
         let notes = contract
             .note_book
@@ -340,10 +815,251 @@ mod tests {
             .unwrap();
 
         assert_eq!(notes.len(), 2);
-        assert!(notes.contains(&posted_note));
-        assert!(notes.contains(&posted_note_2));
+        assert!(notes.contains(&posted_note.id.unwrap().0));
+        assert!(notes.contains(&posted_note_2.id.unwrap().0));
+
+        assert!(notes_2.contains(&posted_note_3.id.unwrap().0));
+    }
+
+    #[test]
+    fn add_note_emits_event() {
+        let mut contract = new_contract();
+
+        set_context("account_id1", NearToken::from_near(1));
+        contract.add_note("title".into(), "body".into(), None);
+
+        let logs = get_logs();
+        let event = logs
+            .iter()
+            .find(|log| log.starts_with("EVENT_JSON:"))
+            .unwrap();
+
+        assert!(event.contains(r#""standard":"notebook""#));
+        assert!(event.contains(r#""event":"note_added""#));
+        assert!(event.contains(r#""account_id":"account_id1","id":"0""#));
+    }
+
+    #[test]
+    fn get_note() {
+        let mut contract = new_contract();
+
+        let account_id = "account_id1";
+        set_context(account_id, NearToken::from_near(1));
+
+        contract.add_note("title".into(), "body".into(), None);
+        contract.add_note("title2".into(), "body2".into(), None);
+
+        let note = contract.get_note(account_id.parse().unwrap(), U64(1));
+
+        assert!(
+            *note
+                == PostedNote::new(
+                    "title2".into(),
+                    "body2".into(),
+                    Some(U64(1)),
+                    account_id.parse().unwrap(),
+                    vec![],
+                )
+        );
+
+        let notes = contract.get_notes(account_id.parse().unwrap(), None, None);
+
+        assert_eq!(notes.len(), 2);
+        assert!(notes[0].title == "title");
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_NOTE_NOT_FOUND")]
+    fn get_note_of_other_account() {
+        let mut contract = new_contract();
+
+        set_context("account_id1", NearToken::from_near(1));
+        contract.add_note("title".into(), "body".into(), None);
+
+        contract.get_note("account_id2".parse().unwrap(), U64(0));
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_NOTE_NOT_FOUND")]
+    fn get_note_past_last_id() {
+        let mut contract = new_contract();
+
+        set_context("account_id1", NearToken::from_near(1));
+        contract.add_note("title".into(), "body".into(), None);
+
+        contract.get_note("account_id1".parse().unwrap(), U64(1));
+    }
+
+    #[test]
+    fn get_note_by_id() {
+        let mut contract = new_contract();
 
-        assert!(notes_2.contains(&posted_note_3));
+        set_context("account_id1", NearToken::from_near(1));
+        contract.add_note("title".into(), "body".into(), None);
+
+        set_context("account_id2", NearToken::from_near(1));
+        contract.add_note("title2".into(), "body2".into(), None);
+
+        // ids are unique across accounts
+        let note = contract.get_note_by_id(U64(1));
+        assert!(note.title == "title2");
+        assert!(note.author == "account_id2".parse::<AccountId>().unwrap());
+
+        set_context("account_id1", NearToken::from_near(0));
+        contract.remove_note(U64(0), None);
+
+        assert!(!contract.note_owners.contains_key(&0));
+    }
+
+    #[test]
+    fn update_note() {
+        let mut contract = new_contract();
+
+        let account_id = "account_id1";
+        set_context(account_id, NearToken::from_near(1));
+
+        contract.add_note("title".into(), "body".into(), None);
+        contract.add_note("title2".into(), "body2".into(), None);
+
+        set_context_at(account_id, NearToken::from_near(0), 10);
+
+        contract.update_note(
+            U64(0),
+            "new title".into(),
+            "a longer body".into(),
+            None,
+            None,
+        );
+
+        let note = contract.get_note(account_id.parse().unwrap(), U64(0));
+
+        assert!(note.id == Some(U64(0)));
+        assert_eq!(note.title, "new title");
+        assert_eq!(note.body, "a longer body");
+        assert_eq!(note.author.as_str(), account_id);
+        assert_eq!(note.created_at, U64(0));
+        assert_eq!(note.updated_at, U64(10));
+
+        let notes = contract.get_notes(account_id.parse().unwrap(), None, None);
+
+        assert_eq!(notes.len(), 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_NOTE_NOT_FOUND")]
+    fn update_note_of_other_account() {
+        let mut contract = new_contract();
+
+        set_context("account_id1", NearToken::from_near(1));
+        contract.add_note("title".into(), "body".into(), None);
+
+        set_context("account_id2", NearToken::from_near(1));
+        contract.update_note(U64(0), "title".into(), "body".into(), None, None);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_NOT_ENOUGH_BALANCE")]
+    fn update_note_without_storage_balance() {
+        let mut contract = new_contract();
+
+        let account_id = "account_id1";
+        set_context(account_id, NearToken::from_near(1));
+        contract.add_note("title".into(), "body".into(), None);
+
+        set_context(account_id, NearToken::from_yoctonear(1));
+        contract.storage_withdraw(None);
+
+        set_context(account_id, NearToken::from_near(0));
+        contract.update_note(
+            U64(0),
+            "title".into(),
+            "a much longer body".into(),
+            None,
+            None,
+        );
+    }
+
+    #[test]
+    fn remove_note() {
+        let mut contract = new_contract();
+
+        let account_id = "account_id1";
+        set_context(account_id, NearToken::from_near(1));
+
+        contract.add_note("title".into(), "body".into(), None);
+        contract.add_note("title2".into(), "body2".into(), None);
+
+        set_context(account_id, NearToken::from_near(0));
+        contract.remove_note(U64(0), None);
+
+        let notes = contract.get_notes(account_id.parse().unwrap(), None, None);
+
+        assert_eq!(notes.len(), 1);
+        assert!(notes[0].id == Some(U64(1)));
+
+        contract.remove_note(U64(1), None);
+
+        assert!(contract
+            .note_book
+            .get(&account_id.parse::<AccountId>().unwrap())
+            .is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_NOTE_NOT_FOUND")]
+    fn remove_note_of_other_account() {
+        let mut contract = new_contract();
+
+        set_context("account_id1", NearToken::from_near(1));
+        contract.add_note("title".into(), "body".into(), None);
+
+        set_context("account_id2", NearToken::from_near(0));
+        contract.remove_note(U64(0), None);
+    }
+
+    #[test]
+    fn remove_note_as_moderator() {
+        let mut contract = new_contract_without_timelock();
+
+        set_context("manager.near", NearToken::from_near(0));
+        execute(
+            &mut contract,
+            Action::GrantRole {
+                account_id: "moderator.near".parse().unwrap(),
+                role: Role::Moderator,
+            },
+        );
+
+        set_context("account_id1", NearToken::from_near(1));
+        contract.add_note("title".into(), "body".into(), None);
+
+        set_context("moderator.near", NearToken::from_near(0));
+        contract.remove_note(U64(0), Some("account_id1".parse().unwrap()));
+
+        assert!(contract
+            .get_notes("account_id1".parse().unwrap(), None, None)
+            .is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_NO_ACCESS")]
+    fn remove_note_not_moderator() {
+        let mut contract = new_contract_without_timelock();
+
+        set_context("manager.near", NearToken::from_near(0));
+        execute(
+            &mut contract,
+            Action::GrantRole {
+                account_id: "pauser.near".parse().unwrap(),
+                role: Role::Pauser,
+            },
+        );
+
+        set_context("account_id1", NearToken::from_near(1));
+        contract.add_note("title".into(), "body".into(), None);
+
+        set_context("pauser.near", NearToken::from_near(0));
+        contract.remove_note(U64(0), Some("account_id1".parse().unwrap()));
     }
 
     // #[test]
@@ -380,12 +1096,4 @@ mod tests {
     //     assert_eq!(notes.len(), 0);
     //     assert!(notes.contains(&posted_note));
     // }
-
-    fn set_context(predecessor: &str, amount: NearToken) {
-        let mut builder = VMContextBuilder::new();
-        builder.predecessor_account_id(predecessor.parse().unwrap());
-        builder.attached_deposit(amount);
-
-        testing_env!(builder.build());
-    }
 }
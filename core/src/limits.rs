@@ -0,0 +1,161 @@
+use near_sdk::near;
+
+use crate::{
+    encryption::EncryptedBody,
+    errors::{ensure, ContractError},
+    Contract, ContractExt,
+};
+
+pub(crate) const DEFAULT_MAX_TITLE_LENGTH: u32 = 256;
+pub(crate) const DEFAULT_MAX_BODY_LENGTH: u32 = 64 * 1024;
+
+// Bounds on the size of notes, in bytes of UTF-8. Admins change them through
+// `Action::SetNoteLimits`
+#[near(serializers = [borsh, json])]
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct NoteLimits {
+    pub max_title_length: u32,
+    // Applies to the ciphertext of encrypted notes as well
+    pub max_body_length: u32,
+}
+
+impl Default for NoteLimits {
+    fn default() -> Self {
+        Self {
+            max_title_length: DEFAULT_MAX_TITLE_LENGTH,
+            max_body_length: DEFAULT_MAX_BODY_LENGTH,
+        }
+    }
+}
+
+impl NoteLimits {
+    pub(crate) fn assert_valid(&self) {
+        ensure!(
+            self.max_title_length > 0 && self.max_body_length > 0,
+            ContractError::InvalidLimits
+        );
+    }
+
+    // Strings are always valid UTF-8 once deserialized, so only their length
+    // and characters are left to check. Titles are a single line, bodies can
+    // span several
+    pub(crate) fn assert_valid_note(
+        &self,
+        title: &str,
+        body: &str,
+        encrypted: Option<&EncryptedBody>,
+    ) {
+        ensure!(!title.trim().is_empty(), ContractError::EmptyTitle);
+        ensure!(
+            title.len() <= self.max_title_length as usize,
+            ContractError::TitleTooLong
+        );
+        ensure!(
+            !title.chars().any(char::is_control),
+            ContractError::InvalidTitle
+        );
+
+        let body_length = encrypted
+            .map_or(body.len(), |encrypted| encrypted.ciphertext.0.len());
+
+        ensure!(
+            body_length <= self.max_body_length as usize,
+            ContractError::BodyTooLong
+        );
+        ensure!(
+            !body
+                .chars()
+                .any(|c| c.is_control() && !matches!(c, '\n' | '\r' | '\t')),
+            ContractError::InvalidBody
+        );
+    }
+}
+
+#[near]
+impl Contract {
+    pub fn get_note_limits(&self) -> NoteLimits {
+        self.note_limits
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use near_sdk::{json_types::U64, NearToken};
+
+    use super::*;
+    use crate::{
+        test_utils::{new_contract, set_context_at},
+        timelock::{Action, DEFAULT_TIMELOCK_DELAY},
+    };
+
+    #[test]
+    #[should_panic(expected = "ERR_EMPTY_TITLE")]
+    fn add_note_with_empty_title() {
+        let mut contract = new_contract();
+
+        set_context_at("account_id1", NearToken::from_near(1), 0);
+        contract.add_note(" ".into(), "body".into(), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_INVALID_BODY")]
+    fn update_note_with_control_characters() {
+        let mut contract = new_contract();
+
+        set_context_at("account_id1", NearToken::from_near(1), 0);
+        contract.add_note("title".into(), "line\nline".into(), None);
+        contract.update_note(
+            U64(0),
+            "title".into(),
+            "bell\u{7}".into(),
+            None,
+            None,
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_BODY_TOO_LONG")]
+    fn set_note_limits() {
+        let mut contract = new_contract();
+
+        set_context_at("manager.near", NearToken::from_near(1), 0);
+        let id = contract.queue_action(Action::SetNoteLimits {
+            limits: NoteLimits {
+                max_title_length: 8,
+                max_body_length: 8,
+            },
+        });
+
+        set_context_at(
+            "manager.near",
+            NearToken::from_near(1),
+            DEFAULT_TIMELOCK_DELAY,
+        );
+        contract.execute_action(id);
+
+        assert!(contract.get_note_limits().max_body_length == 8);
+
+        set_context_at(
+            "account_id1",
+            NearToken::from_near(1),
+            DEFAULT_TIMELOCK_DELAY,
+        );
+        contract.add_note("title".into(), "longer body".into(), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_TITLE_TOO_LONG")]
+    fn add_workspace_note_with_long_title() {
+        let mut contract = new_contract();
+
+        set_context_at("account_id1", NearToken::from_near(1), 0);
+        let workspace_id = contract.create_workspace("team".into());
+
+        contract.add_workspace_note(
+            workspace_id,
+            "t".repeat(DEFAULT_MAX_TITLE_LENGTH as usize + 1),
+            "body".into(),
+            None,
+        );
+    }
+}
@@ -1,76 +1,940 @@
 use near_sdk::{
+    borsh::BorshDeserialize,
     env,
     json_types::{U128, U64},
-    near,
-    store::{IterableMap, IterableSet, LookupMap, LookupSet},
-    AccountId, Gas, NearToken, Promise,
+    log, near,
+    store::{IterableMap, IterableSet, LookupMap, LookupSet, Vector},
+    AccountId, FunctionError, Gas,
 };
 
-use crate::{Contract, ContractExt, PostedNote};
+use crate::{
+    errors::{ensure, ContractError},
+    events::{Migrated, NotebookEvent},
+    exchange::DEFAULT_EXCHANGE_RATE,
+    limits::NoteLimits,
+    ownership::Role,
+    timelock::DEFAULT_TIMELOCK_DELAY,
+    upgrade::DEFAULT_UPGRADE_TTL,
+    Contract, ContractExt, PostedNote, StorageKey,
+};
+
+// Layout version of `Contract`, bumped together with a new migration step.
+// v3 is the single layout of the first release with role based access, and
+// takes in every change made to `Contract` and `PostedNote` until then. Any
+// later change to either needs a new version and a step
+pub(crate) const STATE_VERSION: u64 = 3;
+
+// The version is stored apart from the state so that it can be read without
+// knowing the layout of the state
+const VERSION_KEY: &[u8] = b"VERSION";
+
+// Gas left aside to finish a batch of note migrations
+const MIN_BATCH_GAS: Gas = Gas::from_tgas(20);
 
-const CALL_GAS: Gas = Gas::from_tgas(200);
+// Migration steps taken by `migrate` itself, the rest is left to
+// `migrate_batch`
+const MIGRATE_NOTES_LIMIT: u32 = 50;
 
-//UNCOMMENT FOR MIGRATION HOMEWORK
 #[near(serializers = [borsh, json])]
 #[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
-pub struct OldPostedNote {
+pub struct PostedNoteV1 {
     pub id: Option<U64>,
     pub title: String,
     pub body: String,
 }
 
+// v1: the first release, which didn't store a version
 #[near(serializers = [borsh])]
-struct OldState {
+pub(crate) struct ContractV1 {
     greeting: String,
-    note_book: IterableMap<AccountId, IterableSet<PostedNote>>,
-    // note_book_collections:
-    //     LookUpMapCollections<AccountId, UnorderedSet<PostedNote>>,
+    note_book: IterableMap<AccountId, IterableSet<PostedNoteV1>>,
     ft_id: AccountId,
     w_near_id: AccountId,
     next_entry_id: Option<u64>,
     managers: LookupSet<AccountId>,
     user_points: LookupMap<AccountId, U128>,
-    // version: U64, // MIGRATION HOMEWORK: UNCOMMENT
 }
 
-#[near]
-impl Contract {
-    pub fn update_contract(&self) -> Promise {
-        // Check the caller is authorized to update the code
-        self.assert_manager();
-
-        // Receive the code directly from the input to avoid the
-        // GAS overhead of deserializing parameters
-        let code = env::input().expect("Error: No input").to_vec();
-
-        // Deploy the contract on self
-        Promise::new(env::current_account_id())
-            .deploy_contract(code)
-            .function_call(
-                "migrate".to_string(),
-                vec![],
-                NearToken::from_near(0),
-                CALL_GAS,
-            )
+// v2: adds `version`
+#[near(serializers = [borsh])]
+pub(crate) struct ContractV2 {
+    greeting: String,
+    note_book: IterableMap<AccountId, IterableSet<PostedNoteV1>>,
+    ft_id: AccountId,
+    w_near_id: AccountId,
+    next_entry_id: Option<u64>,
+    managers: LookupSet<AccountId>,
+    user_points: LookupMap<AccountId, U128>,
+    version: U64,
+}
+
+// Notes that are still in the layout of a previous version. They are moved in
+// batches since a large note book can't be migrated within a single call.
+#[near(serializers = [borsh])]
+pub(crate) struct NoteMigration {
+    legacy_note_book: IterableMap<AccountId, IterableSet<PostedNoteV1>>,
+    migrated_notes: u64,
+    // Legacy ids could be picked by clients, so every one of them is checked
+    // before any note is moved and the counter is set past the largest. Ids
+    // given out from then on never belong to a legacy note. The scan stands at
+    // the note `scan_index` of the account `scanned_accounts`
+    scanned_accounts: u32,
+    scan_index: u32,
+    scanned_notes: u64,
+}
+
+// Arguments of `migrate`. Legacy `update_contract` calls it without any, so
+// they are read from the input by hand instead of being declared on the method
+#[near(serializers = [json])]
+#[derive(Default)]
+pub struct MigrateArgs {
+    // Legacy managers that become admins along with the signer of the upgrade
+    pub managers: Vec<AccountId>,
+}
+
+impl MigrateArgs {
+    fn read() -> Self {
+        env::input().filter(|input| !input.is_empty()).map_or_else(
+            Self::default,
+            |input| {
+                near_sdk::serde_json::from_slice(&input)
+                    .unwrap_or_else(|_| ContractError::InvalidMessage.panic())
+            },
+        )
     }
+}
+
+#[near(serializers = [json])]
+pub struct MigrationProgress {
+    pub in_progress: bool,
+    pub scanned_notes: U64,
+    pub migrated_notes: U64,
+    pub remaining_accounts: u32,
+}
+
+// Every layout the state has had, each migrated into the next one
+pub(crate) enum VersionedContract {
+    V1(ContractV1),
+    V2(ContractV2),
+    V3(Box<Contract>),
+}
+
+impl VersionedContract {
+    fn read() -> Self {
+        let version = read_state_version();
+
+        ensure!(version <= STATE_VERSION, ContractError::Downgrade);
 
-    // MIGRATION HOMEWORK: GO OVER OLD POSTED NOTES, INCLUDE AUTHOR AND ADD TO A
-    // NEW STATE_VAR, AND MIGRATE
+        match version {
+            1 => VersionedContract::V1(read_state()),
+            2 => VersionedContract::V2(read_state()),
+            _ => VersionedContract::V3(Box::new(read_state())),
+        }
+    }
+
+    fn version(&self) -> u64 {
+        match self {
+            VersionedContract::V1(_) => 1,
+            VersionedContract::V2(_) => 2,
+            VersionedContract::V3(_) => 3,
+        }
+    }
+
+    // Applies the steps left to reach the current layout
+    fn into_current(self, args: &MigrateArgs) -> Contract {
+        let mut state = self;
+
+        loop {
+            state = match state {
+                VersionedContract::V1(state) => {
+                    VersionedContract::V2(migrate_v1(state))
+                }
+                VersionedContract::V2(state) => VersionedContract::V3(
+                    Box::new(migrate_v2(state, &args.managers)),
+                ),
+                VersionedContract::V3(state) => return *state,
+            };
+        }
+    }
+}
+
+#[near]
+impl Contract {
     #[private]
     #[init(ignore_state)]
     pub fn migrate() -> Self {
-        let old_state: OldState = env::state_read().expect("failed");
-
-        Self {
-            greeting: old_state.greeting,
-            note_book: old_state.note_book,
-            // note_book_collections: old_state.note_book_collections,
-            ft_id: old_state.ft_id,
-            w_near_id: old_state.w_near_id,
-            next_entry_id: old_state.next_entry_id,
-            managers: old_state.managers,
-            user_points: old_state.user_points,
+        let args = MigrateArgs::read();
+        let state = VersionedContract::read();
+        let from_version = state.version();
+
+        let mut this = state.into_current(&args);
+
+        // A failed migration reverts the deploy, so only a small batch of
+        // notes is migrated here
+        this.internal_migrate_notes(MIGRATE_NOTES_LIMIT);
+
+        if from_version != STATE_VERSION {
+            write_state_version();
+
+            NotebookEvent::Migrated(Migrated {
+                from_version: U64(from_version),
+                to_version: U64(STATE_VERSION),
+            })
+            .emit();
+        }
+
+        this
+    }
+
+    pub fn get_version(&self) -> U64 {
+        U64(read_state_version())
+    }
+
+    pub fn migrate_batch(&mut self, limit: u32) -> MigrationProgress {
+        self.assert_role(Role::Upgrader);
+
+        ensure!(self.note_migration.is_some(), ContractError::NoMigration);
+
+        self.internal_migrate_notes(limit);
+
+        self.get_migration_progress()
+    }
+
+    pub fn get_migration_progress(&self) -> MigrationProgress {
+        self.note_migration.as_ref().map_or(
+            MigrationProgress {
+                in_progress: false,
+                scanned_notes: U64(0),
+                migrated_notes: U64(0),
+                remaining_accounts: 0,
+            },
+            |migration| MigrationProgress {
+                in_progress: true,
+                scanned_notes: U64(migration.scanned_notes),
+                migrated_notes: U64(migration.migrated_notes),
+                remaining_accounts: migration.legacy_note_book.len(),
+            },
+        )
+    }
+
+    // Notes can't be written while some of them are still being migrated
+    pub(crate) fn assert_not_migrating(&self) {
+        ensure!(
+            self.note_migration.is_none(),
+            ContractError::MigrationInProgress
+        );
+    }
+
+    // Takes up to `limit` steps, each scanning the id of a legacy note or,
+    // once they were all scanned, moving one into the note index. `store`
+    // collections only write when flushed, so every note is flushed right
+    // away for the gas it takes to be seen before the next one is started
+    fn internal_migrate_notes(&mut self, limit: u32) {
+        let Some(mut migration) = self.note_migration.take() else {
+            return;
+        };
+
+        let mut steps = 0;
+
+        while steps < limit
+            && env::prepaid_gas().saturating_sub(env::used_gas())
+                >= MIN_BATCH_GAS
+        {
+            if migration.scanned_accounts < migration.legacy_note_book.len() {
+                if self.internal_scan_legacy_note(&mut migration) {
+                    steps += 1;
+                }
+
+                continue;
+            }
+
+            let Some((account_id, legacy_notes)) =
+                migration.legacy_note_book.iter_mut().next()
+            else {
+                break;
+            };
+
+            let account_id = account_id.clone();
+            let note = legacy_notes.iter().next().cloned();
+
+            if let Some(note) = &note {
+                legacy_notes.remove(note);
+                legacy_notes.flush();
+            }
+
+            if legacy_notes.is_empty() {
+                migration.legacy_note_book.remove(&account_id);
+            }
+
+            migration.legacy_note_book.flush();
+
+            if let Some(note) = note {
+                self.internal_migrate_note(account_id, note);
+
+                migration.migrated_notes += 1;
+                steps += 1;
+            }
+        }
+
+        if !migration.legacy_note_book.is_empty() {
+            self.note_migration = Some(migration);
+        }
+    }
+
+    // Moves the counter past the id of the next legacy note to scan. Returns
+    // `false` if the account being scanned had no notes left, in which case
+    // the scan moved on to the next account
+    fn internal_scan_legacy_note(
+        &mut self,
+        migration: &mut NoteMigration,
+    ) -> bool {
+        let id = migration
+            .legacy_note_book
+            .values()
+            .nth(migration.scanned_accounts as usize)
+            .and_then(|notes| notes.iter().nth(migration.scan_index as usize))
+            .map(|note| {
+                note.id
+                    .unwrap_or_else(|| ContractError::MissingNoteId.panic())
+                    .0
+            });
+
+        let Some(id) = id else {
+            migration.scanned_accounts += 1;
+            migration.scan_index = 0;
+
+            return false;
+        };
+
+        if self.next_entry_id.unwrap_or(0) <= id {
+            self.next_entry_id = Some(id + 1);
+        }
+
+        migration.scan_index += 1;
+        migration.scanned_notes += 1;
+
+        true
+    }
+
+    fn internal_migrate_note(
+        &mut self,
+        account_id: AccountId,
+        note: PostedNoteV1,
+    ) {
+        let mut id = note
+            .id
+            .unwrap_or_else(|| ContractError::MissingNoteId.panic())
+            .0;
+
+        // Legacy notes of an account could share an id, in which case the
+        // later ones are given new ids rather than overwriting the first. The
+        // counter is past every legacy id by now
+        if self.notes.contains_key(&(account_id.clone(), id)) {
+            let legacy_id = id;
+            id = self.internal_next_note_id();
+
+            log!(
+                "Note {} of {} is migrated as note {}",
+                legacy_id,
+                account_id,
+                id
+            );
+        }
+
+        if let Some(ids) = self.note_book.get_mut(&account_id) {
+            ids.insert(id);
+        } else {
+            let mut ids =
+                IterableSet::new(StorageKey::NoteIds(account_id.clone()));
+            ids.insert(id);
+
+            self.note_book.insert(account_id.clone(), ids);
+        }
+
+        // Legacy notes are authored by the account they are stored under, and
+        // their creation time was never recorded
+        self.notes.insert(
+            (account_id.clone(), id),
+            PostedNote::new(
+                note.title,
+                note.body,
+                Some(U64(id)),
+                account_id.clone(),
+                vec![],
+            ),
+        );
+
+        // Legacy ids could be picked by clients and collide across accounts,
+        // in which case the note is only reachable through its author
+        if !self.note_owners.contains_key(&id) {
+            self.note_owners.insert(id, account_id.clone());
+        }
+
+        self.flush_note_book(&account_id);
+    }
+}
+
+pub(crate) fn write_state_version() {
+    env::storage_write(VERSION_KEY, &STATE_VERSION.to_le_bytes());
+}
+
+// States written before the version was stored apart are told apart by the
+// layout they decode with
+fn read_state_version() -> u64 {
+    if let Some(version) = env::storage_read(VERSION_KEY) {
+        return u64::from_le_bytes(
+            version
+                .try_into()
+                .unwrap_or_else(|_| ContractError::UnknownState.panic()),
+        );
+    }
+
+    let state = env::storage_read(b"STATE")
+        .unwrap_or_else(|| ContractError::NoState.panic());
+
+    if ContractV2::try_from_slice(&state).is_ok() {
+        2
+    } else if ContractV1::try_from_slice(&state).is_ok() {
+        1
+    } else {
+        ContractError::UnknownState.panic()
+    }
+}
+
+// A state that doesn't match the layout of its version is refused rather than
+// misread
+fn read_state<T: BorshDeserialize>() -> T {
+    let state = env::storage_read(b"STATE")
+        .unwrap_or_else(|| ContractError::NoState.panic());
+
+    T::try_from_slice(&state)
+        .unwrap_or_else(|_| ContractError::UnknownState.panic())
+}
+
+fn migrate_v1(state: ContractV1) -> ContractV2 {
+    ContractV2 {
+        greeting: state.greeting,
+        note_book: state.note_book,
+        ft_id: state.ft_id,
+        w_near_id: state.w_near_id,
+        next_entry_id: state.next_entry_id,
+        managers: state.managers,
+        user_points: state.user_points,
+        version: U64(2),
+    }
+}
+
+fn migrate_v2(mut state: ContractV2, managers: &[AccountId]) -> Contract {
+    // Notes are moved out of the per account sets into the id index by
+    // `migrate_batch`
+    let note_migration = (!state.note_book.is_empty()).then(|| NoteMigration {
+        legacy_note_book: state.note_book,
+        migrated_notes: 0,
+        scanned_accounts: 0,
+        scan_index: 0,
+        scanned_notes: 0,
+    });
+
+    // The legacy managers set can't be enumerated, so the managers to migrate
+    // are given to `migrate` and checked against it. The manager that signed
+    // the upgrade always becomes an admin
+    let signer_id = env::signer_account_id();
+    ensure!(state.managers.remove(&signer_id), ContractError::NotManager);
+
+    let mut admins = IterableSet::new(StorageKey::RoleMembers(Role::Admin));
+    admins.insert(signer_id);
+
+    for manager in managers {
+        if state.managers.remove(manager) {
+            admins.insert(manager.clone());
+        } else {
+            log!("{} is not a manager", manager);
+        }
+    }
+
+    let mut roles = IterableMap::new(StorageKey::Roles);
+    roles.insert(Role::Admin, admins);
+
+    let mut this = Contract {
+        greeting: state.greeting,
+        note_book: IterableMap::new(StorageKey::NoteIdsPerUser),
+        notes: LookupMap::new(StorageKey::NotesById),
+        note_owners: LookupMap::new(StorageKey::NoteOwners),
+        note_deposits: LookupMap::new(StorageKey::NoteDeposits),
+        account_tags: LookupMap::new(StorageKey::AccountTags),
+        tagged_notes: LookupMap::new(StorageKey::TaggedNotes),
+        shared_with: LookupMap::new(StorageKey::SharedWith),
+        grantees: LookupMap::new(StorageKey::Grantees),
+        workspaces: LookupMap::new(StorageKey::Workspaces),
+        workspace_notes: LookupMap::new(StorageKey::WorkspaceNotes),
+        workspace_tags: LookupMap::new(StorageKey::WorkspaceTagSets),
+        tagged_workspace_notes: LookupMap::new(
+            StorageKey::TaggedWorkspaceNotes,
+        ),
+        next_workspace_id: 0,
+        note_revisions: LookupMap::new(StorageKey::NoteRevisions),
+        workspace_note_revisions: LookupMap::new(
+            StorageKey::WorkspaceNoteRevisions,
+        ),
+        note_keys: LookupMap::new(StorageKey::NoteKeys),
+        encryption_keys: LookupMap::new(StorageKey::EncryptionKeys),
+        note_limits: NoteLimits::default(),
+        ft_id: state.ft_id,
+        w_near_id: state.w_near_id,
+        exchange_rate: DEFAULT_EXCHANGE_RATE,
+        next_entry_id: state.next_entry_id,
+        roles,
+        user_points: state.user_points,
+        storage_accounts: LookupMap::new(StorageKey::StorageAccounts),
+        account_storage_usage: 0,
+        paused: false,
+        paused_features: IterableSet::new(StorageKey::PausedFeatures),
+        staged_code: LookupMap::new(StorageKey::StagedCode),
+        upgrade_proposals: LookupMap::new(StorageKey::UpgradeProposals),
+        upgrade_threshold: 1,
+        upgrade_ttl: DEFAULT_UPGRADE_TTL,
+        upgrade_history: Vector::new(StorageKey::UpgradeHistory),
+        queued_actions: IterableMap::new(StorageKey::QueuedActions),
+        next_action_id: 0,
+        timelock_delay: DEFAULT_TIMELOCK_DELAY,
+        note_migration,
+    };
+
+    this.measure_account_storage_usage();
+
+    this
+}
+
+#[cfg(test)]
+mod tests {
+    use near_sdk::{testing_env, NearToken};
+
+    use super::*;
+    use crate::test_utils::{context, new_contract};
+
+    #[test]
+    fn migrate_from_v1() {
+        set_context("manager.near");
+
+        let state = ContractV1 {
+            greeting: "howdy".to_string(),
+            note_book: legacy_note_book(1),
+            ft_id: "some_acc.near".parse().unwrap(),
+            w_near_id: "some_acc.near".parse().unwrap(),
+            next_entry_id: Some(1),
+            managers: legacy_managers(),
+            user_points: LookupMap::new(StorageKey::UserPoints),
+        };
+        env::state_write(&state);
+        drop(state);
+
+        assert_eq!(read_state_version(), 1);
+
+        let contract = Contract::migrate();
+
+        assert_migrated(&contract);
+    }
+
+    #[test]
+    fn migrate_from_v2() {
+        set_context("manager.near");
+
+        let state = ContractV2 {
+            greeting: "howdy".to_string(),
+            note_book: legacy_note_book(1),
+            ft_id: "some_acc.near".parse().unwrap(),
+            w_near_id: "some_acc.near".parse().unwrap(),
+            next_entry_id: Some(1),
+            managers: legacy_managers(),
+            user_points: LookupMap::new(StorageKey::UserPoints),
+            version: U64(1),
+        };
+        env::state_write(&state);
+        drop(state);
+
+        assert_eq!(read_state_version(), 2);
+
+        let contract = Contract::migrate();
+
+        assert_migrated(&contract);
+    }
+
+    #[test]
+    fn migrate_legacy_managers() {
+        set_context("manager.near");
+
+        let state = ContractV2 {
+            greeting: "howdy".to_string(),
+            note_book: legacy_note_book(1),
+            ft_id: "some_acc.near".parse().unwrap(),
+            w_near_id: "some_acc.near".parse().unwrap(),
+            next_entry_id: Some(1),
+            managers: legacy_managers(),
+            user_points: LookupMap::new(StorageKey::UserPoints),
+            version: U64(2),
+        };
+        env::state_write(&state);
+        drop(state);
+
+        testing_env!(context("manager.near", NearToken::from_near(0))
+            .signer_account_id("manager.near".parse().unwrap())
+            .input(br#"{"managers":["manager2.near","account_id1"]}"#.to_vec())
+            .build());
+
+        let contract = Contract::migrate();
+
+        // accounts that weren't managers are left out
+        let admins = contract.get_role_members(Role::Admin, None, None);
+        assert_eq!(admins.len(), 2);
+        assert!(contract.has_role("manager.near".parse().unwrap(), Role::Admin));
+        assert!(
+            contract.has_role("manager2.near".parse().unwrap(), Role::Admin)
+        );
+        assert!(!contract.has_role("account_id1".parse().unwrap(), Role::Admin));
+    }
+
+    #[test]
+    fn migrate_v1_step() {
+        set_context("manager.near");
+
+        let state = migrate_v1(ContractV1 {
+            greeting: "howdy".to_string(),
+            note_book: legacy_note_book(1),
+            ft_id: "some_acc.near".parse().unwrap(),
+            w_near_id: "some_acc.near".parse().unwrap(),
+            next_entry_id: Some(1),
+            managers: legacy_managers(),
+            user_points: LookupMap::new(StorageKey::UserPoints),
+        });
+
+        assert_eq!(state.version, U64(2));
+        assert_eq!(state.greeting, "howdy");
+        assert_eq!(state.note_book.len(), 1);
+    }
+
+    #[test]
+    fn migrate_batch() {
+        set_context("manager.near");
+
+        let mut contract = migrate_v2(
+            ContractV2 {
+                greeting: "howdy".to_string(),
+                note_book: legacy_note_book(3),
+                ft_id: "some_acc.near".parse().unwrap(),
+                w_near_id: "some_acc.near".parse().unwrap(),
+                next_entry_id: Some(3),
+                managers: legacy_managers(),
+                user_points: LookupMap::new(StorageKey::UserPoints),
+                version: U64(2),
+            },
+            &[],
+        );
+
+        let progress = contract.get_migration_progress();
+        assert!(progress.in_progress);
+        assert_eq!(progress.remaining_accounts, 1);
+
+        // every legacy id is scanned before any note is moved
+        let progress = contract.migrate_batch(3);
+        assert!(progress.in_progress);
+        assert_eq!(progress.scanned_notes, U64(3));
+        assert_eq!(progress.migrated_notes, U64(0));
+
+        let progress = contract.migrate_batch(2);
+        assert!(progress.in_progress);
+        assert_eq!(progress.migrated_notes, U64(2));
+
+        let progress = contract.migrate_batch(2);
+        assert!(!progress.in_progress);
+
+        let notes =
+            contract.get_notes("account_id1".parse().unwrap(), None, None);
+        assert_eq!(notes.len(), 3);
+
+        assert!(contract.get_note_by_id(U64(2)).title == "title");
+    }
+
+    #[test]
+    fn migrate_duplicate_ids() {
+        set_context("manager.near");
+
+        let account_id: AccountId = "account_id1".parse().unwrap();
+
+        let mut note_book = legacy_note_book(2);
+        note_book
+            .get_mut(&account_id)
+            .unwrap()
+            .insert(PostedNoteV1 {
+                id: Some(U64(1)),
+                title: "title2".to_string(),
+                body: "body2".to_string(),
+            });
+
+        let mut contract = migrate_v2(
+            ContractV2 {
+                greeting: "howdy".to_string(),
+                note_book,
+                ft_id: "some_acc.near".parse().unwrap(),
+                w_near_id: "some_acc.near".parse().unwrap(),
+                next_entry_id: Some(2),
+                managers: legacy_managers(),
+                user_points: LookupMap::new(StorageKey::UserPoints),
+                version: U64(2),
+            },
+            &[],
+        );
+
+        contract.migrate_batch(u32::MAX);
+
+        // the note sharing an id is kept under a new one
+        let notes = contract.get_notes(account_id, None, None);
+        let mut ids: Vec<u64> =
+            notes.iter().map(|note| note.id.unwrap().0).collect();
+        ids.sort();
+        assert_eq!(ids, vec![0, 1, 2]);
+
+        assert!(notes.iter().any(|note| note.title == "title2"));
+        assert_eq!(contract.get_note_by_id(U64(2)).id, Some(U64(2)));
+    }
+
+    #[test]
+    fn migrate_colliding_ids() {
+        set_context("manager.near");
+
+        let account_id1: AccountId = "account_id1".parse().unwrap();
+        let account_id2: AccountId = "account_id2".parse().unwrap();
+
+        // the legacy counter lags behind ids picked by clients, and the
+        // first account has two notes under the same id
+        let mut notes1 =
+            IterableSet::new(StorageKey::Notes(account_id1.clone()));
+        for title in ["title", "title2"] {
+            notes1.insert(PostedNoteV1 {
+                id: Some(U64(1)),
+                title: title.to_string(),
+                body: "body".to_string(),
+            });
+        }
+
+        let mut notes2 =
+            IterableSet::new(StorageKey::Notes(account_id2.clone()));
+        notes2.insert(PostedNoteV1 {
+            id: Some(U64(2)),
+            title: "title3".to_string(),
+            body: "body".to_string(),
+        });
+
+        let mut note_book = IterableMap::new(StorageKey::NotesPerUser);
+        note_book.insert(account_id1.clone(), notes1);
+        note_book.insert(account_id2.clone(), notes2);
+
+        let mut contract = migrate_v2(
+            ContractV2 {
+                greeting: "howdy".to_string(),
+                note_book,
+                ft_id: "some_acc.near".parse().unwrap(),
+                w_near_id: "some_acc.near".parse().unwrap(),
+                next_entry_id: Some(2),
+                managers: legacy_managers(),
+                user_points: LookupMap::new(StorageKey::UserPoints),
+                version: U64(2),
+            },
+            &[],
+        );
+
+        while contract.migrate_batch(1).in_progress {}
+
+        // the duplicate is given an id past every legacy one, and the note of
+        // the second account keeps its own
+        let mut ids: Vec<u64> = contract
+            .get_notes(account_id1.clone(), None, None)
+            .iter()
+            .map(|note| note.id.unwrap().0)
+            .collect();
+        ids.sort();
+        assert_eq!(ids, vec![1, 3]);
+
+        assert_eq!(contract.get_note_by_id(U64(2)).author, account_id2);
+        assert_eq!(contract.get_note_by_id(U64(3)).author, account_id1);
+
+        // new notes are numbered from there
+        assert_eq!(contract.next_entry_id, Some(4));
+    }
+
+    #[test]
+    fn migrate_batch_gas_budget() {
+        set_context("manager.near");
+
+        let mut contract = migrate_v2(
+            ContractV2 {
+                greeting: "howdy".to_string(),
+                note_book: legacy_note_book(200),
+                ft_id: "some_acc.near".parse().unwrap(),
+                w_near_id: "some_acc.near".parse().unwrap(),
+                next_entry_id: Some(200),
+                managers: legacy_managers(),
+                user_points: LookupMap::new(StorageKey::UserPoints),
+                version: U64(2),
+            },
+            &[],
+        );
+
+        // both the scan and the moves stop once the gas left is down to the
+        // reserve, with the writes of the migrated notes counted
+        set_gas_budget("manager.near", Gas::from_tgas(2));
+
+        let progress = contract.migrate_batch(u32::MAX);
+        assert!(progress.in_progress);
+        assert!(progress.scanned_notes.0 > 0);
+        assert!(progress.scanned_notes.0 < 200);
+
+        set_context("manager.near");
+        let progress =
+            contract.migrate_batch((200 - progress.scanned_notes.0) as u32);
+        assert_eq!(progress.scanned_notes, U64(200));
+        assert_eq!(progress.migrated_notes, U64(0));
+
+        set_gas_budget("manager.near", Gas::from_tgas(5));
+
+        let progress = contract.migrate_batch(u32::MAX);
+        assert!(progress.in_progress);
+        assert!(progress.migrated_notes.0 > 0);
+        assert!(progress.migrated_notes.0 < 200);
+
+        set_context("manager.near");
+        while contract.migrate_batch(u32::MAX).in_progress {}
+
+        let notes =
+            contract.get_notes("account_id1".parse().unwrap(), None, None);
+        assert_eq!(notes.len(), 200);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_MIGRATION_IN_PROGRESS")]
+    fn add_note_while_migrating() {
+        set_context("manager.near");
+
+        let mut contract = migrate_v2(
+            ContractV2 {
+                greeting: "howdy".to_string(),
+                note_book: legacy_note_book(1),
+                ft_id: "some_acc.near".parse().unwrap(),
+                w_near_id: "some_acc.near".parse().unwrap(),
+                next_entry_id: Some(1),
+                managers: legacy_managers(),
+                user_points: LookupMap::new(StorageKey::UserPoints),
+                version: U64(2),
+            },
+            &[],
+        );
+
+        contract.add_note("title".into(), "body".into(), None);
+    }
+
+    #[test]
+    fn migrate_current_version() {
+        set_context("manager.near");
+
+        let contract = new_contract();
+        env::state_write(&contract);
+        drop(contract);
+
+        let contract = Contract::migrate();
+
+        assert_eq!(contract.get_version(), U64(STATE_VERSION));
+        assert!(contract.has_role("manager.near".parse().unwrap(), Role::Admin));
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_DOWNGRADE")]
+    fn migrate_downgrade() {
+        set_context("manager.near");
+
+        let contract = new_contract();
+        env::state_write(&contract);
+        drop(contract);
+
+        env::storage_write(VERSION_KEY, &(STATE_VERSION + 1).to_le_bytes());
+
+        Contract::migrate();
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_UNKNOWN_STATE")]
+    fn migrate_stale_current_version() {
+        set_context("manager.near");
+
+        // a state of an older layout stored under the current version
+        let state = ContractV2 {
+            greeting: "howdy".to_string(),
+            note_book: legacy_note_book(1),
+            ft_id: "some_acc.near".parse().unwrap(),
+            w_near_id: "some_acc.near".parse().unwrap(),
+            next_entry_id: Some(1),
+            managers: legacy_managers(),
+            user_points: LookupMap::new(StorageKey::UserPoints),
             version: U64(2),
+        };
+        env::state_write(&state);
+        drop(state);
+
+        env::storage_write(VERSION_KEY, &STATE_VERSION.to_le_bytes());
+
+        Contract::migrate();
+    }
+
+    fn legacy_note_book(
+        count: u64,
+    ) -> IterableMap<AccountId, IterableSet<PostedNoteV1>> {
+        let account_id: AccountId = "account_id1".parse().unwrap();
+
+        let mut notes = IterableSet::new(StorageKey::Notes(account_id.clone()));
+
+        for id in 0..count {
+            notes.insert(PostedNoteV1 {
+                id: Some(U64(id)),
+                title: "title".to_string(),
+                body: "body".to_string(),
+            });
         }
+
+        let mut note_book = IterableMap::new(StorageKey::NotesPerUser);
+        note_book.insert(account_id, notes);
+
+        note_book
+    }
+
+    fn legacy_managers() -> LookupSet<AccountId> {
+        let mut managers = LookupSet::new(StorageKey::Managers);
+        managers.insert("manager.near".parse().unwrap());
+        managers.insert("manager2.near".parse().unwrap());
+
+        managers
+    }
+
+    fn assert_migrated(contract: &Contract) {
+        assert_eq!(contract.get_version(), U64(STATE_VERSION));
+        assert_eq!(contract.get_greeting(), "howdy");
+
+        let note = contract.get_note("account_id1".parse().unwrap(), U64(0));
+        assert_eq!(note.title, "title");
+        assert_eq!(note.author.as_str(), "account_id1");
+
+        assert!(contract.has_role("manager.near".parse().unwrap(), Role::Admin));
+    }
+
+    // Migrations run on behalf of the account that signed the upgrade
+    fn set_context(signer: &str) {
+        testing_env!(context(signer, NearToken::from_near(0))
+            .signer_account_id(signer.parse().unwrap())
+            .build());
+    }
+
+    // A call left with `gas` on top of the reserve of a batch
+    fn set_gas_budget(signer: &str, gas: Gas) {
+        testing_env!(context(signer, NearToken::from_near(0))
+            .signer_account_id(signer.parse().unwrap())
+            .prepaid_gas(MIN_BATCH_GAS.saturating_add(gas))
+            .build());
     }
 }
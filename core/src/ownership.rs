@@ -1,22 +1,258 @@
-use near_sdk::{env, near, require, AccountId};
+use near_sdk::{
+    env, json_types::U64, near, store::IterableSet, AccountId, FunctionError,
+};
 
-use crate::{Contract, ContractExt};
+use crate::{
+    errors::{ensure, ContractError},
+    events::{NotebookEvent, RoleGranted, RoleRevoked},
+    timelock::Action,
+    Contract, ContractExt, StorageKey,
+};
+
+// Privileged roles. Admins manage the roles and implicitly hold all of them
+#[near(serializers = [borsh, json])]
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Role {
+    Admin,
+    Upgrader,
+    Pauser,
+    // Sets the exchange rate through the timelock
+    RateSetter,
+    // Removes notes of any account
+    Moderator,
+}
 
 #[near]
 impl Contract {
-    pub fn add_manager(&mut self, manager: AccountId) {
-        self.assert_manager();
-        self.managers.insert(manager);
+    // Role changes land through the timelock like every other privileged
+    // action. Both queue the change and return the id of the queued action,
+    // which is executed with `execute_action` once the delay passed
+    pub fn grant_role(&mut self, account_id: AccountId, role: Role) -> U64 {
+        self.queue_action(Action::GrantRole { account_id, role })
+    }
+
+    pub fn revoke_role(&mut self, account_id: AccountId, role: Role) -> U64 {
+        self.queue_action(Action::RevokeRole { account_id, role })
+    }
+
+    pub(crate) fn internal_grant_role(
+        &mut self,
+        account_id: AccountId,
+        role: Role,
+    ) {
+        let inserted = if let Some(members) = self.roles.get_mut(&role) {
+            members.insert(account_id.clone())
+        } else {
+            let mut members = IterableSet::new(StorageKey::RoleMembers(role));
+            members.insert(account_id.clone());
+
+            self.roles.insert(role, members);
+
+            true
+        };
+
+        if inserted {
+            NotebookEvent::RoleGranted(RoleGranted { account_id, role }).emit();
+        }
+    }
+
+    // The last admin can't be revoked, otherwise nobody could manage the
+    // contract anymore
+    pub(crate) fn internal_revoke_role(
+        &mut self,
+        account_id: AccountId,
+        role: Role,
+    ) {
+        let members = self
+            .roles
+            .get_mut(&role)
+            .filter(|members| members.contains(&account_id))
+            .unwrap_or_else(|| ContractError::RoleNotFound.panic());
+
+        ensure!(
+            role != Role::Admin || members.len() > 1,
+            ContractError::LastAdmin
+        );
+
+        members.remove(&account_id);
+
+        NotebookEvent::RoleRevoked(RoleRevoked { account_id, role }).emit();
+    }
+
+    pub fn has_role(&self, account_id: AccountId, role: Role) -> bool {
+        self.internal_has_role(&account_id, role)
+            || self.internal_has_role(&account_id, Role::Admin)
+    }
+
+    // Roles granted to `account_id` explicitly
+    pub fn get_roles(&self, account_id: AccountId) -> Vec<Role> {
+        self.roles
+            .iter()
+            .filter(|(_, members)| members.contains(&account_id))
+            .map(|(role, _)| *role)
+            .collect()
+    }
+
+    pub fn get_role_members(
+        &self,
+        role: Role,
+        from_index: Option<u32>,
+        limit: Option<u32>,
+    ) -> Vec<&AccountId> {
+        self.roles.get(&role).map_or(vec![], |members| {
+            members
+                .iter()
+                .skip(from_index.unwrap_or(0) as usize)
+                .take(limit.unwrap_or(u32::MAX) as usize)
+                .collect()
+        })
+    }
+
+    pub(crate) fn assert_role(&self, role: Role) {
+        ensure!(
+            self.has_role(env::predecessor_account_id(), role),
+            ContractError::MissingRole
+        );
+    }
+
+    fn internal_has_role(&self, account_id: &AccountId, role: Role) -> bool {
+        self.roles
+            .get(&role)
+            .is_some_and(|members| members.contains(account_id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use near_sdk::NearToken;
+
+    use super::*;
+    use crate::test_utils::{
+        execute, new_contract, new_contract_without_timelock, set_context,
+    };
+
+    #[test]
+    fn grant_and_revoke_role() {
+        let mut contract = new_contract_without_timelock();
+
+        set_context("manager.near", NearToken::from_near(0));
+        let id =
+            contract.grant_role("pauser.near".parse().unwrap(), Role::Pauser);
+        contract.execute_action(id);
+
+        assert!(contract.has_role("pauser.near".parse().unwrap(), Role::Pauser));
+        assert!(
+            !contract.has_role("pauser.near".parse().unwrap(), Role::Upgrader)
+        );
+        assert!(
+            contract.get_roles("pauser.near".parse().unwrap())
+                == vec![Role::Pauser]
+        );
+
+        // admins hold every role
+        assert!(
+            contract.has_role("manager.near".parse().unwrap(), Role::Upgrader)
+        );
+
+        let id =
+            contract.revoke_role("pauser.near".parse().unwrap(), Role::Pauser);
+        contract.execute_action(id);
+
+        assert!(
+            !contract.has_role("pauser.near".parse().unwrap(), Role::Pauser)
+        );
+        assert!(contract
+            .get_role_members(Role::Pauser, None, None)
+            .is_empty());
+    }
+
+    #[test]
+    fn get_role_members() {
+        let mut contract = new_contract_without_timelock();
+
+        set_context("manager.near", NearToken::from_near(0));
+        execute(
+            &mut contract,
+            Action::GrantRole {
+                account_id: "manager2.near".parse().unwrap(),
+                role: Role::Admin,
+            },
+        );
+
+        let admins = contract.get_role_members(Role::Admin, None, None);
+        assert_eq!(admins.len(), 2);
+        assert_eq!(admins[1].as_str(), "manager2.near");
+
+        let admins = contract.get_role_members(Role::Admin, Some(1), Some(1));
+        assert_eq!(admins.len(), 1);
+        assert_eq!(admins[0].as_str(), "manager2.near");
+
+        execute(
+            &mut contract,
+            Action::RevokeRole {
+                account_id: "manager.near".parse().unwrap(),
+                role: Role::Admin,
+            },
+        );
+
+        let admins = contract.get_role_members(Role::Admin, None, None);
+        assert_eq!(admins.len(), 1);
+        assert_eq!(admins[0].as_str(), "manager2.near");
     }
 
-    pub fn remove_manager(&mut self, manager: AccountId) {
-        todo!()
+    #[test]
+    #[should_panic(expected = "ERR_TIMELOCKED")]
+    fn grant_role_timelocked() {
+        let mut contract = new_contract();
+
+        set_context("manager.near", NearToken::from_near(0));
+        let id =
+            contract.grant_role("pauser.near".parse().unwrap(), Role::Pauser);
+
+        assert!(
+            !contract.has_role("pauser.near".parse().unwrap(), Role::Pauser)
+        );
+
+        contract.execute_action(id);
     }
 
-    pub(crate) fn assert_manager(&self) {
-        require!(
-            self.managers.contains(&env::predecessor_account_id()),
-            "ERR_NOT_MANAGER"
+    #[test]
+    #[should_panic(expected = "ERR_LAST_ADMIN")]
+    fn revoke_last_admin() {
+        let mut contract = new_contract_without_timelock();
+
+        set_context("manager.near", NearToken::from_near(0));
+        execute(
+            &mut contract,
+            Action::RevokeRole {
+                account_id: "manager.near".parse().unwrap(),
+                role: Role::Admin,
+            },
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_MISSING_ROLE")]
+    fn grant_role_not_admin() {
+        let mut contract = new_contract_without_timelock();
+
+        set_context("manager.near", NearToken::from_near(0));
+        execute(
+            &mut contract,
+            Action::GrantRole {
+                account_id: "pauser.near".parse().unwrap(),
+                role: Role::Pauser,
+            },
+        );
+
+        // only admins manage roles
+        set_context("pauser.near", NearToken::from_near(0));
+        execute(
+            &mut contract,
+            Action::GrantRole {
+                account_id: "account_id1".parse().unwrap(),
+                role: Role::Pauser,
+            },
         );
     }
 }
@@ -1,54 +1,322 @@
-use std::{
-    fmt::{self, Display, Formatter},
-    str::FromStr,
-};
+use near_sdk::{env, json_types::U64, near};
 
-use near_contract_standards::fungible_token::receiver::FungibleTokenReceiver;
-use near_sdk::{
-    env, json_types::U128, near, require, AccountId, NearToken, PromiseOrValue,
+use crate::{
+    errors::{ensure, ContractError},
+    events::{NotebookEvent, Paused},
+    ownership::Role,
+    timelock::Action,
+    Contract, ContractExt,
 };
 
-use crate::{Contract, ContractExt, PostedNote, MIN_NOTE_DEPOSIT};
+// Parts of the contract that can be paused on their own
+#[near(serializers = [borsh, json])]
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Feature {
+    Notes,
+    FtDeposits,
+    Exchange,
+    Greetings,
+}
 
+// Pausing takes effect right away, while unpausing goes through the timelock
+// so that users can exit before the contract starts again
 pub trait Pausable {
-    fn toggle_pause(&mut self);
+    fn pause(&mut self);
+    fn toggle_pause(&mut self) -> Option<U64>;
     fn is_paused(&self) -> bool;
     fn assert_not_paused(&self);
+    fn pause_feature(&mut self, feature: Feature);
+    fn get_paused_features(&self) -> Vec<Feature>;
+    fn is_feature_paused(&self, feature: Feature) -> bool;
+    fn assert_feature_not_paused(&self, feature: Feature);
 }
 
 #[near]
 impl Pausable for Contract {
-    fn toggle_pause(&mut self) {
-        todo!("Implement toggle_paus")
+    fn pause(&mut self) {
+        self.assert_role(Role::Pauser);
+
+        if !self.paused {
+            self.paused = true;
+
+            NotebookEvent::Paused(Paused {
+                account_id: env::predecessor_account_id(),
+                feature: None,
+                paused: true,
+            })
+            .emit();
+        }
+    }
+
+    // Pauses right away, or queues the unpause and returns the id of the
+    // queued action
+    fn toggle_pause(&mut self) -> Option<U64> {
+        self.assert_role(Role::Admin);
+
+        if self.paused {
+            Some(self.queue_action(Action::Unpause))
+        } else {
+            self.pause();
+
+            None
+        }
     }
 
     fn is_paused(&self) -> bool {
-        todo!("Implement is_paused")
+        self.paused
     }
 
     fn assert_not_paused(&self) {
-        todo!("Implement assert_not_paused")
+        ensure!(!self.paused, ContractError::Paused);
+    }
+
+    fn pause_feature(&mut self, feature: Feature) {
+        self.assert_role(Role::Pauser);
+
+        if self.paused_features.insert(feature) {
+            NotebookEvent::Paused(Paused {
+                account_id: env::predecessor_account_id(),
+                feature: Some(feature),
+                paused: true,
+            })
+            .emit();
+        }
+    }
+
+    fn get_paused_features(&self) -> Vec<Feature> {
+        self.paused_features.iter().copied().collect()
+    }
+
+    // A feature is also paused while the whole contract is
+    fn is_feature_paused(&self, feature: Feature) -> bool {
+        self.paused || self.paused_features.contains(&feature)
+    }
+
+    fn assert_feature_not_paused(&self, feature: Feature) {
+        ensure!(!self.is_feature_paused(feature), ContractError::Paused);
+    }
+}
+
+#[near]
+impl Contract {
+    pub(crate) fn internal_unpause(&mut self) {
+        if self.paused {
+            self.paused = false;
+
+            NotebookEvent::Paused(Paused {
+                account_id: env::predecessor_account_id(),
+                feature: None,
+                paused: false,
+            })
+            .emit();
+        }
+    }
+
+    pub(crate) fn internal_unpause_feature(&mut self, feature: Feature) {
+        if self.paused_features.remove(&feature) {
+            NotebookEvent::Paused(Paused {
+                account_id: env::predecessor_account_id(),
+                feature: Some(feature),
+                paused: false,
+            })
+            .emit();
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use near_sdk::{test_utils::VMContextBuilder, testing_env, NearToken};
+    use near_contract_standards::fungible_token::receiver::FungibleTokenReceiver;
+    use near_sdk::{
+        json_types::{U128, U64},
+        NearToken, PromiseOrValue,
+    };
 
     use super::*;
+    use crate::{
+        test_utils::{
+            execute, new_contract, new_contract_without_timelock, set_context,
+            set_context_at,
+        },
+        timelock::{Action, DEFAULT_TIMELOCK_DELAY},
+    };
 
     #[test]
     fn paused() {
-        todo!("Implement paused test")
+        let mut contract = new_contract_without_timelock();
+
+        assert!(!contract.is_paused());
+
+        set_context("manager.near", NearToken::from_near(0));
+        contract.pause();
+
+        assert!(contract.is_paused());
+
+        let id = contract.queue_action(Action::Unpause);
+        contract.execute_action(id);
+
+        assert!(!contract.is_paused());
+    }
+
+    #[test]
+    fn pause_feature() {
+        let mut contract = new_contract_without_timelock();
+
+        set_context("manager.near", NearToken::from_near(0));
+        contract.pause_feature(Feature::Exchange);
+
+        assert!(contract.is_feature_paused(Feature::Exchange));
+        assert!(!contract.is_feature_paused(Feature::Notes));
+        assert!(!contract.is_paused());
+        assert!(contract.get_paused_features() == vec![Feature::Exchange]);
+
+        // notes keep working
+        set_context("account_id1", NearToken::from_near(1));
+        contract.add_note("title".into(), "body".into(), None);
+
+        set_context("manager.near", NearToken::from_near(0));
+        let id = contract.queue_action(Action::UnpauseFeature {
+            feature: Feature::Exchange,
+        });
+        contract.execute_action(id);
+
+        assert!(!contract.is_feature_paused(Feature::Exchange));
+        assert!(contract.get_paused_features().is_empty());
+
+        // the global pause covers every feature
+        contract.pause();
+
+        assert!(contract.is_feature_paused(Feature::Greetings));
     }
 
-    // more tests here
+    #[test]
+    fn toggle_pause() {
+        let mut contract = new_contract();
+
+        set_context("manager.near", NearToken::from_near(0));
+        assert!(contract.toggle_pause().is_none());
+
+        assert!(contract.is_paused());
+
+        // the pause is only lifted once the timelock passed
+        let id = contract.toggle_pause().unwrap();
+
+        assert!(contract.is_paused());
+
+        set_context_at(
+            "manager.near",
+            NearToken::from_near(0),
+            DEFAULT_TIMELOCK_DELAY,
+        );
+        contract.execute_action(id);
+
+        assert!(!contract.is_paused());
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_MISSING_ROLE")]
+    fn toggle_pause_not_admin() {
+        let mut contract = new_contract_without_timelock();
+
+        set_context("manager.near", NearToken::from_near(0));
+        execute(
+            &mut contract,
+            Action::GrantRole {
+                account_id: "pauser.near".parse().unwrap(),
+                role: Role::Pauser,
+            },
+        );
+
+        set_context("pauser.near", NearToken::from_near(0));
+        contract.toggle_pause();
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_PAUSED")]
+    fn set_greeting_paused() {
+        let mut contract = new_contract_without_timelock();
+
+        set_context("manager.near", NearToken::from_near(0));
+        contract.pause_feature(Feature::Greetings);
+
+        contract.set_greeting("howdy".into());
+    }
+
+    #[test]
+    fn ft_on_transfer_feature_paused() {
+        let mut contract = new_contract_without_timelock();
+
+        set_context("manager.near", NearToken::from_near(0));
+        contract.pause_feature(Feature::Exchange);
+
+        set_context("some_acc.near", NearToken::from_near(0));
+        let res = contract.ft_on_transfer(
+            "account_id1".parse().unwrap(),
+            U128(100),
+            r#""Exchange""#.to_string(),
+        );
+
+        match res {
+            PromiseOrValue::Value(amount) => assert_eq!(amount.0, 100),
+            PromiseOrValue::Promise(_) => panic!("expected a value"),
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_MISSING_ROLE")]
+    fn pause_not_pauser() {
+        let mut contract = new_contract_without_timelock();
+
+        set_context("account_id1", NearToken::from_near(0));
+        contract.pause();
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_PAUSED")]
+    fn add_note_paused() {
+        let mut contract = new_contract_without_timelock();
+
+        set_context("manager.near", NearToken::from_near(0));
+        contract.pause();
+
+        set_context("account_id1", NearToken::from_near(1));
+        contract.add_note("title".into(), "body".into(), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_PAUSED")]
+    fn remove_note_paused() {
+        let mut contract = new_contract_without_timelock();
+
+        set_context("account_id1", NearToken::from_near(1));
+        contract.add_note("title".into(), "body".into(), None);
+
+        set_context("manager.near", NearToken::from_near(0));
+        contract.pause_feature(Feature::Notes);
+
+        // note deposits can't be refunded through `remove_note` either
+        set_context("account_id1", NearToken::from_near(0));
+        contract.remove_note(U64(0), None);
+    }
+
+    #[test]
+    fn ft_on_transfer_paused() {
+        let mut contract = new_contract_without_timelock();
+
+        set_context("manager.near", NearToken::from_near(0));
+        contract.pause();
 
-    fn set_context(predecessor: &str, amount: NearToken) {
-        let mut builder = VMContextBuilder::new();
-        builder.predecessor_account_id(predecessor.parse().unwrap());
-        builder.attached_deposit(amount);
+        set_context("some_acc.near", NearToken::from_near(0));
+        let res = contract.ft_on_transfer(
+            "account_id1".parse().unwrap(),
+            U128(100),
+            r#""Exchange""#.to_string(),
+        );
 
-        testing_env!(builder.build());
+        // the whole amount is refunded
+        match res {
+            PromiseOrValue::Value(amount) => assert_eq!(amount.0, 100),
+            PromiseOrValue::Promise(_) => panic!("expected a value"),
+        }
     }
 }
@@ -0,0 +1,368 @@
+use near_sdk::{
+    env,
+    json_types::U64,
+    near,
+    store::{IterableMap, IterableSet},
+    AccountId,
+};
+
+use crate::{
+    errors::{ensure, ContractError},
+    events::{AccessGranted, AccessRevoked, NotebookEvent},
+    pausable::{Feature, Pausable},
+    Contract, ContractExt, StorageKey,
+};
+
+// Accounts a single note or a whole note book can be shared with, bounded so
+// that removing a note can always revoke its grants
+pub(crate) const MAX_GRANTEES: u32 = 50;
+
+// Contract state is public, so reading can't be enforced on chain. A read
+// grant makes a note show up in `get_shared_with`, a write grant also lets the
+// grantee edit and remove it
+#[near(serializers = [borsh, json])]
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Permission {
+    Read,
+    Write,
+}
+
+// A note, or the whole note book when `id` is `None`, shared by `account_id`
+#[near(serializers = [json])]
+pub struct SharedNote {
+    pub account_id: AccountId,
+    pub id: Option<U64>,
+    pub permission: Permission,
+}
+
+#[near]
+impl Contract {
+    // The grant is stored at the expense of the author, so the attached
+    // deposit is added to their storage balance
+    #[payable]
+    pub fn grant_access(
+        &mut self,
+        grantee: AccountId,
+        id: Option<U64>,
+        permission: Permission,
+    ) {
+        self.assert_feature_not_paused(Feature::Notes);
+
+        let account_id = env::predecessor_account_id();
+
+        ensure!(grantee != account_id, ContractError::SelfGrant);
+
+        if let Some(id) = id {
+            ensure!(
+                self.notes.contains_key(&(account_id.clone(), id.0)),
+                ContractError::NoteNotFound
+            );
+        }
+
+        let deposit = env::attached_deposit().as_yoctonear();
+
+        if deposit != 0 {
+            self.internal_storage_deposit(&account_id, deposit);
+        }
+
+        let storage_usage = env::storage_usage();
+
+        self.internal_grant_access(&account_id, &grantee, id, permission);
+
+        self.internal_settle_storage(&account_id, storage_usage);
+
+        NotebookEvent::AccessGranted(AccessGranted {
+            account_id,
+            grantee,
+            id,
+            permission,
+        })
+        .emit();
+    }
+
+    pub fn revoke_access(&mut self, grantee: AccountId, id: Option<U64>) {
+        let account_id = env::predecessor_account_id();
+
+        let storage_usage = env::storage_usage();
+
+        ensure!(
+            self.internal_revoke_access(&account_id, &grantee, id),
+            ContractError::GrantNotFound
+        );
+
+        self.internal_settle_storage(&account_id, storage_usage);
+    }
+
+    // Notes and note books other accounts have shared with `account_id`
+    pub fn get_shared_with(
+        &self,
+        account_id: AccountId,
+        from_index: Option<u32>,
+        limit: Option<u32>,
+    ) -> Vec<SharedNote> {
+        self.shared_with.get(&account_id).map_or(vec![], |shared| {
+            shared
+                .iter()
+                .skip(from_index.unwrap_or(0) as usize)
+                .take(limit.unwrap_or(u32::MAX) as usize)
+                .map(|((account_id, id), permission)| SharedNote {
+                    account_id: account_id.clone(),
+                    id: id.map(U64),
+                    permission: *permission,
+                })
+                .collect()
+        })
+    }
+
+    // The access `grantee` has to a note of `account_id`, either through the
+    // note itself or through the whole note book
+    pub fn get_permission(
+        &self,
+        account_id: AccountId,
+        id: U64,
+        grantee: AccountId,
+    ) -> Option<Permission> {
+        if grantee == account_id {
+            return Some(Permission::Write);
+        }
+
+        let shared = self.shared_with.get(&grantee)?;
+
+        let note = shared.get(&(account_id.clone(), Some(id.0)));
+        let note_book = shared.get(&(account_id, None));
+
+        note.max(note_book).copied()
+    }
+
+    pub(crate) fn assert_can_write(&self, account_id: &AccountId, id: u64) {
+        ensure!(
+            self.get_permission(
+                account_id.clone(),
+                U64(id),
+                env::predecessor_account_id()
+            ) == Some(Permission::Write),
+            ContractError::NoAccess
+        );
+    }
+
+    // Revokes every grant on note `id`, called once the note is removed
+    pub(crate) fn internal_revoke_note_access(
+        &mut self,
+        account_id: &AccountId,
+        id: u64,
+    ) {
+        let grantees: Vec<AccountId> = self
+            .grantees
+            .get(&(account_id.clone(), Some(id)))
+            .map_or(vec![], |grantees| grantees.iter().cloned().collect());
+
+        for grantee in grantees {
+            self.internal_revoke_access(account_id, &grantee, Some(U64(id)));
+        }
+    }
+
+    // Grants are indexed both by grantee, for `get_shared_with`, and by note,
+    // to revoke them when the note is removed
+    fn internal_grant_access(
+        &mut self,
+        account_id: &AccountId,
+        grantee: &AccountId,
+        id: Option<U64>,
+        permission: Permission,
+    ) {
+        let note = (account_id.clone(), id.map(|id| id.0));
+
+        if let Some(shared) = self.shared_with.get_mut(grantee) {
+            shared.insert(note.clone(), permission);
+            shared.flush();
+        } else {
+            let mut shared =
+                IterableMap::new(StorageKey::SharedNotes(grantee.clone()));
+            shared.insert(note.clone(), permission);
+            shared.flush();
+
+            self.shared_with.insert(grantee.clone(), shared);
+        }
+
+        if let Some(grantees) = self.grantees.get_mut(&note) {
+            grantees.insert(grantee.clone());
+            ensure!(
+                grantees.len() <= MAX_GRANTEES,
+                ContractError::TooManyGrantees
+            );
+            grantees.flush();
+        } else {
+            let mut grantees = IterableSet::new(StorageKey::GranteeIds(
+                note.0.clone(),
+                note.1,
+            ));
+            grantees.insert(grantee.clone());
+            grantees.flush();
+
+            self.grantees.insert(note, grantees);
+        }
+
+        self.shared_with.flush();
+        self.grantees.flush();
+    }
+
+    fn internal_revoke_access(
+        &mut self,
+        account_id: &AccountId,
+        grantee: &AccountId,
+        id: Option<U64>,
+    ) -> bool {
+        let note = (account_id.clone(), id.map(|id| id.0));
+
+        let Some(shared) = self.shared_with.get_mut(grantee) else {
+            return false;
+        };
+
+        if shared.remove(&note).is_none() {
+            return false;
+        }
+
+        shared.flush();
+
+        if shared.is_empty() {
+            self.shared_with.remove(grantee);
+        }
+
+        if let Some(grantees) = self.grantees.get_mut(&note) {
+            grantees.remove(grantee);
+            grantees.flush();
+
+            if grantees.is_empty() {
+                self.grantees.remove(&note);
+            }
+        }
+
+        self.shared_with.flush();
+        self.grantees.flush();
+
+        NotebookEvent::AccessRevoked(AccessRevoked {
+            account_id: account_id.clone(),
+            grantee: grantee.clone(),
+            id,
+        })
+        .emit();
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use near_sdk::NearToken;
+
+    use super::*;
+    use crate::test_utils::{new_contract, set_context};
+
+    #[test]
+    fn grant_write_access() {
+        let mut contract = new_contract();
+
+        set_context("account_id1", NearToken::from_near(1));
+        contract.add_note("title".into(), "body".into(), None);
+        contract.grant_access(
+            "account_id2".parse().unwrap(),
+            Some(U64(0)),
+            Permission::Write,
+        );
+
+        let shared = contract.get_shared_with(
+            "account_id2".parse().unwrap(),
+            None,
+            None,
+        );
+        assert_eq!(shared.len(), 1);
+        assert_eq!(shared[0].account_id.as_str(), "account_id1");
+        assert!(shared[0].id == Some(U64(0)));
+
+        set_context("account_id2", NearToken::from_near(1));
+        contract.update_note(
+            U64(0),
+            "new title".into(),
+            "body".into(),
+            None,
+            Some("account_id1".parse().unwrap()),
+        );
+
+        let note = contract.get_note("account_id1".parse().unwrap(), U64(0));
+        assert_eq!(note.title, "new title");
+        assert_eq!(note.author.as_str(), "account_id1");
+
+        contract.remove_note(U64(0), Some("account_id1".parse().unwrap()));
+
+        // grants on a removed note go away with it
+        assert!(contract
+            .get_shared_with("account_id2".parse().unwrap(), None, None)
+            .is_empty());
+    }
+
+    #[test]
+    fn grant_note_book_access() {
+        let mut contract = new_contract();
+
+        set_context("account_id1", NearToken::from_near(1));
+        contract.add_note("title".into(), "body".into(), None);
+        contract.add_note("title2".into(), "body2".into(), None);
+        contract.grant_access(
+            "account_id2".parse().unwrap(),
+            None,
+            Permission::Read,
+        );
+        contract.grant_access(
+            "account_id2".parse().unwrap(),
+            Some(U64(1)),
+            Permission::Write,
+        );
+
+        let permission = |contract: &Contract, id| {
+            contract.get_permission(
+                "account_id1".parse().unwrap(),
+                U64(id),
+                "account_id2".parse().unwrap(),
+            )
+        };
+
+        assert!(permission(&contract, 0) == Some(Permission::Read));
+        assert!(permission(&contract, 1) == Some(Permission::Write));
+
+        contract.revoke_access("account_id2".parse().unwrap(), None);
+
+        assert!(permission(&contract, 0).is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_NO_ACCESS")]
+    fn update_note_with_read_access() {
+        let mut contract = new_contract();
+
+        set_context("account_id1", NearToken::from_near(1));
+        contract.add_note("title".into(), "body".into(), None);
+        contract.grant_access(
+            "account_id2".parse().unwrap(),
+            Some(U64(0)),
+            Permission::Read,
+        );
+
+        set_context("account_id2", NearToken::from_near(1));
+        contract.update_note(
+            U64(0),
+            "new title".into(),
+            "body".into(),
+            None,
+            Some("account_id1".parse().unwrap()),
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_GRANT_NOT_FOUND")]
+    fn revoke_missing_access() {
+        let mut contract = new_contract();
+
+        set_context("account_id1", NearToken::from_near(1));
+        contract.revoke_access("account_id2".parse().unwrap(), None);
+    }
+}
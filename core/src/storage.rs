@@ -0,0 +1,363 @@
+use near_contract_standards::storage_management::{
+    StorageBalance, StorageBalanceBounds, StorageManagement,
+};
+use near_sdk::{
+    assert_one_yocto, env, log, near, AccountId, FunctionError, NearToken,
+    Promise,
+};
+
+use crate::{
+    errors::{ensure, ContractError},
+    pausable::{Feature, Pausable},
+    Contract, ContractExt,
+};
+
+// NEAR deposited by an account to pay for the storage of its notes
+#[near(serializers = [borsh])]
+#[derive(Default)]
+pub struct StorageAccount {
+    pub total: u128,
+    pub available: u128,
+}
+
+impl StorageAccount {
+    fn balance(&self) -> StorageBalance {
+        StorageBalance {
+            total: NearToken::from_yoctonear(self.total),
+            available: NearToken::from_yoctonear(self.available),
+        }
+    }
+}
+
+#[near]
+impl StorageManagement for Contract {
+    #[payable]
+    fn storage_deposit(
+        &mut self,
+        account_id: Option<AccountId>,
+        registration_only: Option<bool>,
+    ) -> StorageBalance {
+        let amount = env::attached_deposit().as_yoctonear();
+        let account_id = account_id.unwrap_or_else(env::predecessor_account_id);
+
+        let is_registered = self.storage_accounts.contains_key(&account_id);
+
+        if registration_only.unwrap_or(false) {
+            let to_refund = if is_registered {
+                amount
+            } else {
+                let min = self.storage_balance_bounds().min.as_yoctonear();
+
+                ensure!(amount >= min, ContractError::BelowMinBalance);
+
+                self.internal_storage_deposit(&account_id, min);
+
+                amount - min
+            };
+
+            if to_refund != 0 {
+                Promise::new(env::predecessor_account_id())
+                    .transfer(NearToken::from_yoctonear(to_refund));
+            }
+        } else {
+            self.internal_storage_deposit(&account_id, amount);
+        }
+
+        self.storage_balance_of(account_id)
+            .unwrap_or_else(|| ContractError::NotRegistered.panic())
+    }
+
+    #[payable]
+    fn storage_withdraw(
+        &mut self,
+        amount: Option<NearToken>,
+    ) -> StorageBalance {
+        assert_one_yocto();
+
+        let account_id = env::predecessor_account_id();
+
+        let storage = self
+            .storage_accounts
+            .get_mut(&account_id)
+            .unwrap_or_else(|| ContractError::NotRegistered.panic());
+
+        let amount =
+            amount.map_or(storage.available, |amount| amount.as_yoctonear());
+
+        ensure!(amount <= storage.available, ContractError::NotEnoughBalance);
+
+        storage.total -= amount;
+        storage.available -= amount;
+
+        let balance = storage.balance();
+
+        if amount != 0 {
+            Promise::new(account_id)
+                .transfer(NearToken::from_yoctonear(amount));
+        }
+
+        balance
+    }
+
+    // Accounts with notes can only unregister with `force`, which removes
+    // all of their notes first
+    #[payable]
+    fn storage_unregister(&mut self, force: Option<bool>) -> bool {
+        assert_one_yocto();
+
+        let account_id = env::predecessor_account_id();
+
+        if !self.storage_accounts.contains_key(&account_id) {
+            log!("The account {} is not registered", account_id);
+            return false;
+        }
+
+        if let Some(ids) = self.note_book.get(&account_id) {
+            ensure!(force.unwrap_or(false), ContractError::HasNotes);
+
+            // Removing notes refunds their token deposits, which has to stop
+            // while notes are paused
+            self.assert_feature_not_paused(Feature::Notes);
+
+            let ids: Vec<u64> = ids.iter().copied().collect();
+
+            for id in ids {
+                self.internal_remove_note(account_id.clone(), id);
+            }
+        }
+
+        let storage = self
+            .storage_accounts
+            .remove(&account_id)
+            .unwrap_or_else(|| ContractError::NotRegistered.panic());
+
+        let to_refund = storage.available
+            + self.storage_balance_bounds().min.as_yoctonear();
+
+        Promise::new(account_id).transfer(NearToken::from_yoctonear(to_refund));
+
+        true
+    }
+
+    fn storage_balance_bounds(&self) -> StorageBalanceBounds {
+        StorageBalanceBounds {
+            min: env::storage_byte_cost()
+                .saturating_mul(self.account_storage_usage.into()),
+            max: None,
+        }
+    }
+
+    fn storage_balance_of(
+        &self,
+        account_id: AccountId,
+    ) -> Option<StorageBalance> {
+        self.storage_accounts
+            .get(&account_id)
+            .map(StorageAccount::balance)
+    }
+}
+
+#[near]
+impl Contract {
+    // Adds `amount` to the storage balance of `account_id`, registering it
+    // first if needed
+    pub(crate) fn internal_storage_deposit(
+        &mut self,
+        account_id: &AccountId,
+        amount: u128,
+    ) {
+        let min = self.storage_balance_bounds().min.as_yoctonear();
+
+        if let Some(storage) = self.storage_accounts.get_mut(account_id) {
+            storage.total += amount;
+            storage.available += amount;
+        } else {
+            ensure!(amount >= min, ContractError::BelowMinBalance);
+
+            self.storage_accounts.insert(
+                account_id.clone(),
+                StorageAccount {
+                    total: amount,
+                    available: amount - min,
+                },
+            );
+        }
+
+        self.storage_accounts.flush();
+    }
+
+    // Charges the storage used since `initial_storage` to the storage balance
+    // of `account_id`, or credits it back if storage was freed. Notes posted
+    // before storage management was introduced were paid for directly, so
+    // accounts without a balance get their freed storage refunded directly.
+    pub(crate) fn internal_settle_storage(
+        &mut self,
+        account_id: &AccountId,
+        initial_storage: u64,
+    ) {
+        let storage_usage = env::storage_usage();
+        let byte_cost = env::storage_byte_cost().as_yoctonear();
+
+        if storage_usage > initial_storage {
+            let storage_cost =
+                byte_cost * (storage_usage - initial_storage) as u128;
+
+            let storage = self
+                .storage_accounts
+                .get_mut(account_id)
+                .unwrap_or_else(|| ContractError::NotRegistered.panic());
+
+            storage.available = storage
+                .available
+                .checked_sub(storage_cost)
+                .unwrap_or_else(|| ContractError::NotEnoughBalance.panic());
+        } else if storage_usage < initial_storage {
+            let freed = byte_cost * (initial_storage - storage_usage) as u128;
+
+            if let Some(storage) = self.storage_accounts.get_mut(account_id) {
+                storage.available += freed;
+            } else {
+                Promise::new(account_id.clone())
+                    .transfer(NearToken::from_yoctonear(freed));
+            }
+        }
+    }
+
+    // Measures the storage taken by the balance of an account with the
+    // longest possible id, which is the minimum storage balance
+    pub(crate) fn measure_account_storage_usage(&mut self) {
+        let initial_storage = env::storage_usage();
+        let tmp_account_id: AccountId = "a"
+            .repeat(64)
+            .parse()
+            .unwrap_or_else(|_| ContractError::InvalidAccountId.panic());
+
+        self.storage_accounts
+            .insert(tmp_account_id.clone(), StorageAccount::default());
+        self.storage_accounts.flush();
+
+        self.account_storage_usage = env::storage_usage() - initial_storage;
+
+        self.storage_accounts.remove(&tmp_account_id);
+        self.storage_accounts.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use near_sdk::{json_types::U64, NearToken};
+
+    use super::*;
+    use crate::test_utils::{new_contract, set_context};
+
+    #[test]
+    fn storage_deposit() {
+        let mut contract = new_contract();
+
+        let min = contract.storage_balance_bounds().min;
+        assert!(min.as_yoctonear() > 0);
+
+        set_context("account_id1", NearToken::from_near(1));
+        let balance = contract.storage_deposit(None, None);
+
+        assert_eq!(balance.total, NearToken::from_near(1));
+        assert_eq!(
+            balance.available.as_yoctonear(),
+            NearToken::from_near(1).as_yoctonear() - min.as_yoctonear()
+        );
+
+        // notes draw from the storage balance
+        set_context("account_id1", NearToken::from_near(0));
+        contract.add_note("title".into(), "body".into(), None);
+
+        let balance = contract
+            .storage_balance_of("account_id1".parse().unwrap())
+            .unwrap();
+
+        assert_eq!(balance.total, NearToken::from_near(1));
+        assert!(
+            balance.available.as_yoctonear()
+                < NearToken::from_near(1).as_yoctonear() - min.as_yoctonear()
+        );
+
+        // removing the note frees its storage again
+        contract.remove_note(U64(0), None);
+
+        let balance = contract
+            .storage_balance_of("account_id1".parse().unwrap())
+            .unwrap();
+
+        assert_eq!(
+            balance.available.as_yoctonear(),
+            NearToken::from_near(1).as_yoctonear() - min.as_yoctonear()
+        );
+    }
+
+    #[test]
+    fn storage_deposit_registration_only() {
+        let mut contract = new_contract();
+
+        set_context("account_id1", NearToken::from_near(1));
+        let balance = contract.storage_deposit(None, Some(true));
+
+        assert_eq!(balance.total, contract.storage_balance_bounds().min);
+        assert_eq!(balance.available, NearToken::from_near(0));
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_NOT_ENOUGH_BALANCE")]
+    fn add_note_without_storage_balance() {
+        let mut contract = new_contract();
+
+        set_context("account_id1", NearToken::from_near(1));
+        contract.storage_deposit(None, Some(true));
+
+        set_context("account_id1", NearToken::from_near(0));
+        contract.add_note("title".into(), "body".into(), None);
+    }
+
+    #[test]
+    fn storage_withdraw() {
+        let mut contract = new_contract();
+
+        set_context("account_id1", NearToken::from_near(1));
+        contract.add_note("title".into(), "body".into(), None);
+
+        set_context("account_id1", NearToken::from_yoctonear(1));
+        let balance = contract.storage_withdraw(None);
+
+        assert_eq!(balance.available, NearToken::from_near(0));
+        assert!(balance.total < NearToken::from_near(1));
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_HAS_NOTES")]
+    fn storage_unregister_with_notes() {
+        let mut contract = new_contract();
+
+        set_context("account_id1", NearToken::from_near(1));
+        contract.add_note("title".into(), "body".into(), None);
+
+        set_context("account_id1", NearToken::from_yoctonear(1));
+        contract.storage_unregister(None);
+    }
+
+    #[test]
+    fn storage_unregister_force() {
+        let mut contract = new_contract();
+
+        set_context("account_id1", NearToken::from_near(1));
+        contract.add_note("title".into(), "body".into(), None);
+
+        set_context("account_id1", NearToken::from_yoctonear(1));
+        assert!(contract.storage_unregister(Some(true)));
+
+        assert!(contract
+            .storage_balance_of("account_id1".parse().unwrap())
+            .is_none());
+        assert!(contract
+            .note_book
+            .get(&"account_id1".parse::<AccountId>().unwrap())
+            .is_none());
+    }
+}
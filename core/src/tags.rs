@@ -0,0 +1,486 @@
+use near_sdk::{
+    borsh::BorshSerialize,
+    json_types::U64,
+    near,
+    store::{IterableSet, LookupMap},
+    AccountId, FunctionError,
+};
+
+use crate::{
+    errors::{ensure, ContractError},
+    Contract, ContractExt, PostedNote, StorageKey,
+};
+
+pub(crate) const MAX_TAGS: usize = 10;
+pub(crate) const MAX_TAG_LENGTH: usize = 32;
+
+// Tags are kept as given, but have to be non-empty, short and unique
+pub(crate) fn assert_valid_tags(tags: &[String]) {
+    ensure!(tags.len() <= MAX_TAGS, ContractError::TooManyTags);
+
+    for (i, tag) in tags.iter().enumerate() {
+        ensure!(
+            !tag.is_empty() && tag.len() <= MAX_TAG_LENGTH,
+            ContractError::InvalidTag
+        );
+        ensure!(!tags[..i].contains(tag), ContractError::DuplicateTag);
+    }
+}
+
+// Owners of a tag index, accounts for their own notes and workspaces for
+// theirs
+pub(crate) trait TagOwner: BorshSerialize + Ord + Clone {
+    fn tags_key(&self) -> StorageKey;
+    fn tagged_note_ids_key(&self, tag: &str) -> StorageKey;
+}
+
+impl TagOwner for AccountId {
+    fn tags_key(&self) -> StorageKey {
+        StorageKey::Tags(self.clone())
+    }
+
+    fn tagged_note_ids_key(&self, tag: &str) -> StorageKey {
+        StorageKey::TaggedNoteIds(self.clone(), tag.to_string())
+    }
+}
+
+impl TagOwner for u64 {
+    fn tags_key(&self) -> StorageKey {
+        StorageKey::WorkspaceTags(*self)
+    }
+
+    fn tagged_note_ids_key(&self, tag: &str) -> StorageKey {
+        StorageKey::WorkspaceTaggedNoteIds(*self, tag.to_string())
+    }
+}
+
+// Splits a change of tags into the tags removed and the tags added
+pub(crate) fn diff_tags(
+    old_tags: Vec<String>,
+    new_tags: &[String],
+) -> (Vec<String>, Vec<String>) {
+    let (kept, removed): (Vec<_>, Vec<_>) =
+        old_tags.into_iter().partition(|tag| new_tags.contains(tag));
+    let added = new_tags
+        .iter()
+        .filter(|tag| !kept.contains(tag))
+        .cloned()
+        .collect();
+
+    (removed, added)
+}
+
+#[near]
+impl Contract {
+    // Tags used by the notes of `account_id`
+    pub fn get_tags(
+        &self,
+        account_id: AccountId,
+        from_index: Option<u32>,
+        limit: Option<u32>,
+    ) -> Vec<&String> {
+        paginate_tags(&self.account_tags, &account_id, from_index, limit)
+    }
+
+    pub fn get_notes_by_tag(
+        &self,
+        account_id: AccountId,
+        tag: String,
+        from_index: Option<u32>,
+        limit: Option<u32>,
+    ) -> Vec<&PostedNote> {
+        let Some(ids) = self.tagged_notes.get(&(account_id.clone(), tag))
+        else {
+            return vec![];
+        };
+
+        ids.iter()
+            .skip(from_index.unwrap_or(0) as usize)
+            .take(limit.unwrap_or(u32::MAX) as usize)
+            .map(|id| {
+                self.notes
+                    .get(&(account_id.clone(), *id))
+                    .unwrap_or_else(|| ContractError::NoteNotFound.panic())
+            })
+            .collect()
+    }
+
+    // Tags used by the notes of a workspace
+    pub fn get_workspace_tags(
+        &self,
+        workspace_id: U64,
+        from_index: Option<u32>,
+        limit: Option<u32>,
+    ) -> Vec<&String> {
+        paginate_tags(&self.workspace_tags, &workspace_id.0, from_index, limit)
+    }
+
+    pub fn get_workspace_notes_by_tag(
+        &self,
+        workspace_id: U64,
+        tag: String,
+        from_index: Option<u32>,
+        limit: Option<u32>,
+    ) -> Vec<&PostedNote> {
+        let Some(ids) = self.tagged_workspace_notes.get(&(workspace_id.0, tag))
+        else {
+            return vec![];
+        };
+
+        ids.iter()
+            .skip(from_index.unwrap_or(0) as usize)
+            .take(limit.unwrap_or(u32::MAX) as usize)
+            .map(|id| {
+                self.workspace_notes
+                    .get(&(workspace_id.0, *id))
+                    .unwrap_or_else(|| ContractError::NoteNotFound.panic())
+            })
+            .collect()
+    }
+
+    pub(crate) fn internal_tag_note(
+        &mut self,
+        account_id: &AccountId,
+        id: u64,
+        tags: &[String],
+    ) {
+        tag_note(
+            &mut self.account_tags,
+            &mut self.tagged_notes,
+            account_id,
+            id,
+            tags,
+        );
+    }
+
+    pub(crate) fn internal_untag_note(
+        &mut self,
+        account_id: &AccountId,
+        id: u64,
+        tags: &[String],
+    ) {
+        untag_note(
+            &mut self.account_tags,
+            &mut self.tagged_notes,
+            account_id,
+            id,
+            tags,
+        );
+    }
+
+    pub(crate) fn internal_tag_workspace_note(
+        &mut self,
+        workspace_id: u64,
+        id: u64,
+        tags: &[String],
+    ) {
+        tag_note(
+            &mut self.workspace_tags,
+            &mut self.tagged_workspace_notes,
+            &workspace_id,
+            id,
+            tags,
+        );
+    }
+
+    pub(crate) fn internal_untag_workspace_note(
+        &mut self,
+        workspace_id: u64,
+        id: u64,
+        tags: &[String],
+    ) {
+        untag_note(
+            &mut self.workspace_tags,
+            &mut self.tagged_workspace_notes,
+            &workspace_id,
+            id,
+            tags,
+        );
+    }
+}
+
+fn paginate_tags<'a, K: TagOwner>(
+    owner_tags: &'a LookupMap<K, IterableSet<String>>,
+    owner: &K,
+    from_index: Option<u32>,
+    limit: Option<u32>,
+) -> Vec<&'a String> {
+    owner_tags.get(owner).map_or(vec![], |tags| {
+        tags.iter()
+            .skip(from_index.unwrap_or(0) as usize)
+            .take(limit.unwrap_or(u32::MAX) as usize)
+            .collect()
+    })
+}
+
+// Adds note `id` to the index of each tag. Like the note book, the index is
+// flushed right away so that its storage is charged with the note
+fn tag_note<K: TagOwner>(
+    owner_tags: &mut LookupMap<K, IterableSet<String>>,
+    tagged_notes: &mut LookupMap<(K, String), IterableSet<u64>>,
+    owner: &K,
+    id: u64,
+    tags: &[String],
+) {
+    for tag in tags {
+        let key = (owner.clone(), tag.clone());
+
+        if let Some(ids) = tagged_notes.get_mut(&key) {
+            ids.insert(id);
+            ids.flush();
+            continue;
+        }
+
+        let mut ids = IterableSet::new(owner.tagged_note_ids_key(tag));
+        ids.insert(id);
+        ids.flush();
+
+        tagged_notes.insert(key, ids);
+
+        if let Some(tags) = owner_tags.get_mut(owner) {
+            tags.insert(tag.clone());
+            tags.flush();
+        } else {
+            let mut tags = IterableSet::new(owner.tags_key());
+            tags.insert(tag.clone());
+            tags.flush();
+
+            owner_tags.insert(owner.clone(), tags);
+        }
+    }
+
+    tagged_notes.flush();
+    owner_tags.flush();
+}
+
+// Removes note `id` from the index of each tag, dropping the tags that are no
+// longer used
+fn untag_note<K: TagOwner>(
+    owner_tags: &mut LookupMap<K, IterableSet<String>>,
+    tagged_notes: &mut LookupMap<(K, String), IterableSet<u64>>,
+    owner: &K,
+    id: u64,
+    tags: &[String],
+) {
+    for tag in tags {
+        let key = (owner.clone(), tag.clone());
+
+        let Some(ids) = tagged_notes.get_mut(&key) else {
+            continue;
+        };
+
+        ids.remove(&id);
+        ids.flush();
+
+        if !ids.is_empty() {
+            continue;
+        }
+
+        tagged_notes.remove(&key);
+
+        if let Some(tags) = owner_tags.get_mut(owner) {
+            tags.remove(tag);
+            tags.flush();
+
+            if tags.is_empty() {
+                owner_tags.remove(owner);
+            }
+        }
+    }
+
+    tagged_notes.flush();
+    owner_tags.flush();
+}
+
+#[cfg(test)]
+mod tests {
+    use near_sdk::NearToken;
+
+    use super::*;
+    use crate::test_utils::{new_contract, set_context};
+
+    #[test]
+    fn get_notes_by_tag() {
+        let mut contract = new_contract();
+
+        set_context("account_id1", NearToken::from_near(1));
+        contract.add_note("a".into(), "body".into(), Some(tags(&["work"])));
+        contract.add_note(
+            "b".into(),
+            "body".into(),
+            Some(tags(&["work", "home"])),
+        );
+        contract.add_note("c".into(), "body".into(), None);
+
+        let account_id: AccountId = "account_id1".parse().unwrap();
+
+        let notes = contract.get_notes_by_tag(
+            account_id.clone(),
+            "work".into(),
+            None,
+            None,
+        );
+        assert_eq!(notes.len(), 2);
+
+        let notes = contract.get_notes_by_tag(
+            account_id.clone(),
+            "work".into(),
+            Some(1),
+            Some(1),
+        );
+        assert_eq!(notes.len(), 1);
+        assert_eq!(notes[0].title, "b");
+
+        assert_eq!(contract.get_tags(account_id.clone(), None, None).len(), 2);
+
+        let tags = contract.get_tags(account_id.clone(), Some(1), Some(1));
+        assert_eq!(tags.len(), 1);
+        assert_eq!(tags[0], "home");
+
+        // tags of other accounts are separate
+        assert!(contract
+            .get_notes_by_tag(
+                "account_id2".parse().unwrap(),
+                "work".into(),
+                None,
+                None
+            )
+            .is_empty());
+    }
+
+    #[test]
+    fn update_and_remove_tags() {
+        let mut contract = new_contract();
+
+        set_context("account_id1", NearToken::from_near(1));
+        contract.add_note(
+            "a".into(),
+            "body".into(),
+            Some(tags(&["work", "home"])),
+        );
+
+        contract.update_note(
+            U64(0),
+            "a".into(),
+            "body".into(),
+            Some(tags(&["home", "ideas"])),
+            None,
+        );
+
+        let account_id: AccountId = "account_id1".parse().unwrap();
+
+        let note = contract.get_note(account_id.clone(), U64(0));
+        assert!(note.tags == tags(&["home", "ideas"]));
+
+        assert!(contract
+            .get_notes_by_tag(account_id.clone(), "work".into(), None, None)
+            .is_empty());
+        assert_eq!(
+            contract
+                .get_notes_by_tag(
+                    account_id.clone(),
+                    "ideas".into(),
+                    None,
+                    None
+                )
+                .len(),
+            1
+        );
+
+        contract.remove_note(U64(0), None);
+
+        assert!(contract.get_tags(account_id.clone(), None, None).is_empty());
+        assert!(contract
+            .get_notes_by_tag(account_id, "home".into(), None, None)
+            .is_empty());
+    }
+
+    #[test]
+    fn workspace_tags() {
+        let mut contract = new_contract();
+
+        set_context("owner.near", NearToken::from_near(1));
+        let workspace_id = contract.create_workspace("team".into());
+
+        let id = contract.add_workspace_note(
+            workspace_id,
+            "a".into(),
+            "body".into(),
+            Some(tags(&["work", "home"])),
+        );
+        contract.add_workspace_note(
+            workspace_id,
+            "b".into(),
+            "body".into(),
+            Some(tags(&["work"])),
+        );
+
+        let notes = contract.get_workspace_notes_by_tag(
+            workspace_id,
+            "work".into(),
+            None,
+            None,
+        );
+        assert_eq!(notes.len(), 2);
+
+        contract.update_workspace_note(
+            workspace_id,
+            id,
+            "a".into(),
+            "body".into(),
+            Some(tags(&["ideas"])),
+        );
+
+        let workspace_tags =
+            contract.get_workspace_tags(workspace_id, None, None);
+        assert!(workspace_tags == vec!["work", "ideas"]);
+
+        // tags of workspaces are kept apart from the ones of their members
+        assert!(contract
+            .get_tags("owner.near".parse().unwrap(), None, None)
+            .is_empty());
+
+        contract.remove_workspace_note(workspace_id, id);
+
+        assert!(contract
+            .get_workspace_notes_by_tag(
+                workspace_id,
+                "ideas".into(),
+                None,
+                None
+            )
+            .is_empty());
+        assert_eq!(
+            contract.get_workspace_tags(workspace_id, None, None).len(),
+            1
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_TOO_MANY_TAGS")]
+    fn too_many_tags() {
+        let mut contract = new_contract();
+
+        let tags = (0..=MAX_TAGS).map(|i| i.to_string()).collect();
+
+        set_context("account_id1", NearToken::from_near(1));
+        contract.add_note("a".into(), "body".into(), Some(tags));
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_DUPLICATE_TAG")]
+    fn duplicate_tags() {
+        let mut contract = new_contract();
+
+        set_context("account_id1", NearToken::from_near(1));
+        contract.add_note(
+            "a".into(),
+            "body".into(),
+            Some(tags(&["work", "work"])),
+        );
+    }
+
+    fn tags(tags: &[&str]) -> Vec<String> {
+        tags.iter().map(|tag| tag.to_string()).collect()
+    }
+}
@@ -0,0 +1,60 @@
+// Fixtures shared by the unit tests of every module
+use near_sdk::{
+    json_types::U64, test_utils::VMContextBuilder, testing_env, NearToken,
+};
+
+use crate::{timelock::Action, Contract};
+
+// Tokens are faked by "some_acc.near", and "manager.near" is the only admin
+pub(crate) fn new_contract() -> Contract {
+    Contract::new(
+        "some_acc.near".parse().unwrap(),
+        "some_acc.near".parse().unwrap(),
+        vec!["manager.near".parse().unwrap()],
+        None,
+    )
+}
+
+// Timelocked actions can be executed as soon as they are queued
+pub(crate) fn new_contract_without_timelock() -> Contract {
+    Contract::new(
+        "some_acc.near".parse().unwrap(),
+        "some_acc.near".parse().unwrap(),
+        vec!["manager.near".parse().unwrap()],
+        Some(U64(0)),
+    )
+}
+
+// A call from `predecessor` attaching `amount`, left open for modules that
+// need more of the context
+pub(crate) fn context(
+    predecessor: &str,
+    amount: NearToken,
+) -> VMContextBuilder {
+    let mut builder = VMContextBuilder::new();
+    builder.predecessor_account_id(predecessor.parse().unwrap());
+    builder.attached_deposit(amount);
+
+    builder
+}
+
+pub(crate) fn set_context(predecessor: &str, amount: NearToken) {
+    testing_env!(context(predecessor, amount).build());
+}
+
+pub(crate) fn set_context_at(
+    predecessor: &str,
+    amount: NearToken,
+    block_timestamp: u64,
+) {
+    testing_env!(context(predecessor, amount)
+        .block_timestamp(block_timestamp)
+        .build());
+}
+
+// Queues and executes `action`, which only lands right away on a contract
+// without timelock
+pub(crate) fn execute(contract: &mut Contract, action: Action) {
+    let id = contract.queue_action(action);
+    contract.execute_action(id);
+}
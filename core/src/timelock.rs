@@ -0,0 +1,313 @@
+use near_sdk::{
+    env,
+    json_types::{U128, U64},
+    near, AccountId, FunctionError,
+};
+
+use crate::{
+    errors::{ensure, ContractError},
+    events::{ActionCancelled, ActionExecuted, ActionQueued, NotebookEvent},
+    limits::NoteLimits,
+    ownership::Role,
+    pausable::Feature,
+    Contract, ContractExt,
+};
+
+// Sensitive actions only land a day after being queued by default
+pub(crate) const DEFAULT_TIMELOCK_DELAY: u64 = 24 * 60 * 60 * 1_000_000_000;
+
+// Privileged changes that have to wait out the timelock delay. Pausing isn't
+// one of them so that the contract can be stopped right away, and staged code
+// carries its own delay.
+#[near(serializers = [borsh, json])]
+#[derive(Clone)]
+pub enum Action {
+    GrantRole { account_id: AccountId, role: Role },
+    RevokeRole { account_id: AccountId, role: Role },
+    SetUpgradeThreshold { threshold: u32 },
+    SetUpgradeTtl { ttl: U64 },
+    Unpause,
+    UnpauseFeature { feature: Feature },
+    SetTimelockDelay { delay: U64 },
+    SetNoteLimits { limits: NoteLimits },
+    SetExchangeRate { rate: U128 },
+}
+
+impl Action {
+    // The role needed to queue and execute the action
+    fn role(&self) -> Role {
+        match self {
+            Action::GrantRole { .. }
+            | Action::RevokeRole { .. }
+            | Action::SetUpgradeThreshold { .. }
+            | Action::SetUpgradeTtl { .. }
+            | Action::SetTimelockDelay { .. }
+            | Action::SetNoteLimits { .. } => Role::Admin,
+            Action::Unpause | Action::UnpauseFeature { .. } => Role::Pauser,
+            Action::SetExchangeRate { .. } => Role::RateSetter,
+        }
+    }
+}
+
+#[near(serializers = [borsh, json])]
+pub struct QueuedAction {
+    pub id: U64,
+    pub action: Action,
+    pub proposer: AccountId,
+    pub execute_after: U64,
+}
+
+#[near]
+impl Contract {
+    pub fn queue_action(&mut self, action: Action) -> U64 {
+        self.assert_role(action.role());
+
+        let id = self.next_action_id;
+        let queued_action = QueuedAction {
+            id: U64(id),
+            action: action.clone(),
+            proposer: env::predecessor_account_id(),
+            execute_after: U64(
+                env::block_timestamp().saturating_add(self.timelock_delay)
+            ),
+        };
+
+        NotebookEvent::ActionQueued(ActionQueued {
+            account_id: queued_action.proposer.clone(),
+            id: U64(id),
+            action,
+            execute_after: queued_action.execute_after,
+        })
+        .emit();
+
+        self.queued_actions.insert(id, queued_action);
+        self.next_action_id += 1;
+
+        U64(id)
+    }
+
+    // Any account holding a role, or the account that queued it, can cancel
+    // an action during the delay
+    pub fn cancel_action(&mut self, id: U64) {
+        let account_id = env::predecessor_account_id();
+
+        let queued_action = self
+            .queued_actions
+            .get(&id.0)
+            .unwrap_or_else(|| ContractError::ActionNotFound.panic());
+
+        ensure!(
+            queued_action.proposer == account_id
+                || !self.get_roles(account_id.clone()).is_empty(),
+            ContractError::MissingRole
+        );
+
+        self.queued_actions.remove(&id.0);
+
+        NotebookEvent::ActionCancelled(ActionCancelled { account_id, id })
+            .emit();
+    }
+
+    pub fn execute_action(&mut self, id: U64) {
+        let queued_action = self
+            .queued_actions
+            .remove(&id.0)
+            .unwrap_or_else(|| ContractError::ActionNotFound.panic());
+
+        self.assert_role(queued_action.action.role());
+
+        ensure!(
+            env::block_timestamp() >= queued_action.execute_after.0,
+            ContractError::Timelocked
+        );
+
+        match queued_action.action {
+            Action::GrantRole { account_id, role } => {
+                self.internal_grant_role(account_id, role);
+            }
+            Action::RevokeRole { account_id, role } => {
+                self.internal_revoke_role(account_id, role);
+            }
+            Action::SetUpgradeThreshold { threshold } => {
+                ensure!(threshold > 0, ContractError::InvalidThreshold);
+
+                self.upgrade_threshold = threshold;
+            }
+            Action::SetUpgradeTtl { ttl } => {
+                self.upgrade_ttl = ttl.0;
+            }
+            Action::Unpause => {
+                self.internal_unpause();
+            }
+            Action::UnpauseFeature { feature } => {
+                self.internal_unpause_feature(feature);
+            }
+            Action::SetTimelockDelay { delay } => {
+                self.timelock_delay = delay.0;
+            }
+            Action::SetNoteLimits { limits } => {
+                limits.assert_valid();
+
+                self.note_limits = limits;
+            }
+            Action::SetExchangeRate { rate } => {
+                ensure!(rate.0 > 0, ContractError::InvalidRate);
+
+                self.exchange_rate = rate.0;
+            }
+        }
+
+        NotebookEvent::ActionExecuted(ActionExecuted {
+            account_id: env::predecessor_account_id(),
+            id,
+        })
+        .emit();
+    }
+
+    pub fn get_queued_actions(
+        &self,
+        from_index: Option<u32>,
+        limit: Option<u32>,
+    ) -> Vec<&QueuedAction> {
+        self.queued_actions
+            .values()
+            .skip(from_index.unwrap_or(0) as usize)
+            .take(limit.unwrap_or(u32::MAX) as usize)
+            .collect()
+    }
+
+    pub fn get_timelock_delay(&self) -> U64 {
+        U64(self.timelock_delay)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use near_sdk::NearToken;
+
+    use super::*;
+    use crate::test_utils::{new_contract, set_context_at};
+
+    #[test]
+    fn execute_action() {
+        let mut contract = new_contract();
+
+        set_context_at("manager.near", NearToken::from_near(0), 0);
+        let id = contract.queue_action(Action::GrantRole {
+            account_id: "pauser.near".parse().unwrap(),
+            role: Role::Pauser,
+        });
+
+        let queued_actions = contract.get_queued_actions(None, None);
+        assert_eq!(queued_actions.len(), 1);
+        assert_eq!(queued_actions[0].execute_after.0, DEFAULT_TIMELOCK_DELAY);
+
+        set_context_at(
+            "manager.near",
+            NearToken::from_near(0),
+            DEFAULT_TIMELOCK_DELAY,
+        );
+        contract.execute_action(id);
+
+        assert!(contract.has_role("pauser.near".parse().unwrap(), Role::Pauser));
+        assert!(contract.get_queued_actions(None, None).is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_TIMELOCKED")]
+    fn execute_action_too_early() {
+        let mut contract = new_contract();
+
+        set_context_at("manager.near", NearToken::from_near(0), 0);
+        let id =
+            contract.queue_action(Action::SetUpgradeThreshold { threshold: 2 });
+
+        set_context_at(
+            "manager.near",
+            NearToken::from_near(0),
+            DEFAULT_TIMELOCK_DELAY - 1,
+        );
+        contract.execute_action(id);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_ACTION_NOT_FOUND")]
+    fn cancel_action() {
+        let mut contract = new_contract();
+
+        set_context_at("manager.near", NearToken::from_near(0), 0);
+        let id =
+            contract.queue_action(Action::SetUpgradeThreshold { threshold: 2 });
+
+        contract.cancel_action(id);
+
+        set_context_at(
+            "manager.near",
+            NearToken::from_near(0),
+            DEFAULT_TIMELOCK_DELAY,
+        );
+        contract.execute_action(id);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_ACTION_NOT_FOUND")]
+    fn cancel_action_other_role() {
+        let mut contract = new_contract();
+
+        set_context_at("manager.near", NearToken::from_near(0), 0);
+        let id = contract.queue_action(Action::GrantRole {
+            account_id: "pauser.near".parse().unwrap(),
+            role: Role::Pauser,
+        });
+
+        set_context_at(
+            "manager.near",
+            NearToken::from_near(0),
+            DEFAULT_TIMELOCK_DELAY,
+        );
+        contract.execute_action(id);
+
+        let id =
+            contract.queue_action(Action::SetUpgradeThreshold { threshold: 2 });
+
+        // holders of any role can cancel actions they couldn't queue
+        set_context_at(
+            "pauser.near",
+            NearToken::from_near(0),
+            DEFAULT_TIMELOCK_DELAY,
+        );
+        contract.cancel_action(id);
+
+        set_context_at(
+            "manager.near",
+            NearToken::from_near(0),
+            2 * DEFAULT_TIMELOCK_DELAY,
+        );
+        contract.execute_action(id);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_MISSING_ROLE")]
+    fn cancel_action_without_role() {
+        let mut contract = new_contract();
+
+        set_context_at("manager.near", NearToken::from_near(0), 0);
+        let id =
+            contract.queue_action(Action::SetUpgradeThreshold { threshold: 2 });
+
+        set_context_at("account_id1", NearToken::from_near(0), 0);
+        contract.cancel_action(id);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_MISSING_ROLE")]
+    fn queue_action_missing_role() {
+        let mut contract = new_contract();
+
+        set_context_at("account_id1", NearToken::from_near(0), 0);
+        contract.queue_action(Action::GrantRole {
+            account_id: "account_id1".parse().unwrap(),
+            role: Role::Admin,
+        });
+    }
+}
@@ -0,0 +1,476 @@
+use near_sdk::{
+    env, is_promise_success,
+    json_types::{Base58CryptoHash, Base64VecU8, U64},
+    near, AccountId, CryptoHash, FunctionError, Gas, NearToken, Promise,
+};
+
+use crate::{
+    errors::{ensure, ContractError},
+    events::{CodeApproved, CodeRemoved, CodeStaged, NotebookEvent, Upgraded},
+    ownership::Role,
+    pausable::Pausable,
+    Contract, ContractExt,
+};
+
+const CALL_GAS: Gas = Gas::from_tgas(200);
+const CALLBACK_GAS: Gas = Gas::from_tgas(20);
+
+// Proposals expire after a week by default
+pub(crate) const DEFAULT_UPGRADE_TTL: u64 = 7 * 24 * 60 * 60 * 1_000_000_000;
+
+// Staged code is read back in chunks small enough for a view
+pub(crate) const MAX_CODE_CHUNK_LENGTH: u32 = 64 * 1024;
+
+// Code staged for an upgrade, deployed once enough upgraders approved it and
+// the timelock delay passed
+#[near(serializers = [borsh, json])]
+pub struct UpgradeProposal {
+    pub proposer: AccountId,
+    pub approvals: Vec<AccountId>,
+    pub deployable_after: U64,
+    pub expires_at: U64,
+}
+
+// Staged code as returned by `get_staged_code`. The code itself is read with
+// `get_staged_code_chunk`, or fetched off chain and checked against the hash
+#[near(serializers = [json])]
+pub struct StagedCode {
+    pub hash: Base58CryptoHash,
+    pub length: U64,
+}
+
+// Outcome of a deploy of staged code
+#[near(serializers = [borsh, json])]
+pub struct UpgradeRecord {
+    pub hash: Base58CryptoHash,
+    pub version: U64,
+    pub timestamp: U64,
+    pub success: bool,
+}
+
+#[near]
+impl Contract {
+    // Stores the code sent as raw input and approves it on behalf of the
+    // proposer. The attached deposit pays for the storage of the code.
+    #[payable]
+    pub fn stage_code(&mut self) -> Base58CryptoHash {
+        self.assert_role(Role::Upgrader);
+
+        // Receive the code directly from the input to avoid the
+        // GAS overhead of deserializing parameters
+        let code = env::input()
+            .unwrap_or_else(|| ContractError::NoInput.panic())
+            .to_vec();
+        let hash = env::sha256_array(&code);
+
+        ensure!(
+            !self.upgrade_proposals.contains_key(&hash),
+            ContractError::AlreadyStaged
+        );
+
+        let initial_storage = env::storage_usage();
+        let proposer = env::predecessor_account_id();
+
+        self.staged_code.insert(hash, code);
+        self.upgrade_proposals.insert(
+            hash,
+            UpgradeProposal {
+                proposer: proposer.clone(),
+                approvals: vec![proposer.clone()],
+                deployable_after: U64(
+                    env::block_timestamp().saturating_add(self.timelock_delay)
+                ),
+                expires_at: U64(
+                    env::block_timestamp().saturating_add(self.upgrade_ttl)
+                ),
+            },
+        );
+        self.staged_code.flush();
+        self.upgrade_proposals.flush();
+
+        let storage_cost = env::storage_byte_cost()
+            .saturating_mul((env::storage_usage() - initial_storage).into());
+        let deposit = env::attached_deposit();
+
+        ensure!(deposit >= storage_cost, ContractError::NotEnoughDeposit);
+
+        let to_refund = deposit.saturating_sub(storage_cost);
+
+        if !to_refund.is_zero() {
+            Promise::new(proposer.clone()).transfer(to_refund);
+        }
+
+        NotebookEvent::CodeStaged(CodeStaged {
+            account_id: proposer,
+            hash: hash.into(),
+        })
+        .emit();
+
+        hash.into()
+    }
+
+    pub fn approve_code(&mut self, hash: Base58CryptoHash) {
+        self.assert_role(Role::Upgrader);
+
+        let account_id = env::predecessor_account_id();
+        let proposal = self.internal_get_proposal(&hash);
+
+        ensure!(
+            !proposal.approvals.contains(&account_id),
+            ContractError::AlreadyApproved
+        );
+
+        proposal.approvals.push(account_id.clone());
+
+        NotebookEvent::CodeApproved(CodeApproved { account_id, hash }).emit();
+    }
+
+    // Cancels a proposal, refunding the storage of the code to its proposer
+    pub fn remove_staged_code(&mut self, hash: Base58CryptoHash) {
+        self.assert_role(Role::Upgrader);
+
+        self.internal_remove_staged_code(&hash.into());
+
+        NotebookEvent::CodeRemoved(CodeRemoved {
+            account_id: env::predecessor_account_id(),
+            hash,
+        })
+        .emit();
+    }
+
+    // Deploys approved code and migrates the state to it. The code stays
+    // staged until the migration is known to have succeeded.
+    pub fn deploy_staged(&mut self, hash: Base58CryptoHash) -> Promise {
+        self.assert_role(Role::Upgrader);
+        self.assert_not_paused();
+        self.assert_not_migrating();
+
+        let proposal = self.internal_get_proposal(&hash);
+
+        ensure!(
+            env::block_timestamp() >= proposal.deployable_after.0,
+            ContractError::Timelocked
+        );
+
+        let approvals = proposal.approvals.clone();
+
+        // Approvals of accounts that lost the role since don't count
+        let approvals = approvals
+            .into_iter()
+            .filter(|account_id| {
+                self.has_role(account_id.clone(), Role::Upgrader)
+            })
+            .count() as u32;
+
+        ensure!(
+            approvals >= self.upgrade_threshold,
+            ContractError::NotEnoughApprovals
+        );
+
+        let code = self
+            .staged_code
+            .get(&CryptoHash::from(hash))
+            .unwrap_or_else(|| ContractError::ProposalNotFound.panic())
+            .clone();
+
+        // Deploy the contract on self
+        Promise::new(env::current_account_id())
+            .deploy_contract(code)
+            .function_call(
+                "migrate".to_string(),
+                vec![],
+                NearToken::from_near(0),
+                CALL_GAS,
+            )
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(CALLBACK_GAS)
+                    .resolve_deploy_staged(hash),
+            )
+    }
+
+    // Runs on the new code if the migration succeeded, and on the old one
+    // otherwise since the failed deploy is reverted
+    #[private]
+    pub fn resolve_deploy_staged(&mut self, hash: Base58CryptoHash) -> bool {
+        let success = is_promise_success();
+
+        if success {
+            self.internal_remove_staged_code(&hash.into());
+        }
+
+        self.upgrade_history.push(UpgradeRecord {
+            hash,
+            version: self.get_version(),
+            timestamp: U64(env::block_timestamp()),
+            success,
+        });
+
+        NotebookEvent::Upgraded(Upgraded {
+            hash,
+            version: self.get_version(),
+            success,
+        })
+        .emit();
+
+        success
+    }
+
+    pub fn get_upgrade_history(
+        &self,
+        from_index: Option<u32>,
+        limit: Option<u32>,
+    ) -> Vec<&UpgradeRecord> {
+        self.upgrade_history
+            .iter()
+            .skip(from_index.unwrap_or(0) as usize)
+            .take(limit.unwrap_or(u32::MAX) as usize)
+            .collect()
+    }
+
+    pub fn get_upgrade_threshold(&self) -> u32 {
+        self.upgrade_threshold
+    }
+
+    pub fn get_upgrade_proposal(
+        &self,
+        hash: Base58CryptoHash,
+    ) -> Option<&UpgradeProposal> {
+        self.upgrade_proposals.get(&CryptoHash::from(hash))
+    }
+
+    pub fn get_staged_code(
+        &self,
+        hash: Base58CryptoHash,
+    ) -> Option<StagedCode> {
+        self.staged_code
+            .get(&CryptoHash::from(hash))
+            .map(|code| StagedCode {
+                hash,
+                length: U64(code.len() as u64),
+            })
+    }
+
+    // Bytes of staged code from `from_index`, at most `MAX_CODE_CHUNK_LENGTH`
+    // of them
+    pub fn get_staged_code_chunk(
+        &self,
+        hash: Base58CryptoHash,
+        from_index: U64,
+        limit: Option<u32>,
+    ) -> Option<Base64VecU8> {
+        let limit = limit.map_or(MAX_CODE_CHUNK_LENGTH, |limit| {
+            limit.min(MAX_CODE_CHUNK_LENGTH)
+        });
+
+        self.staged_code.get(&CryptoHash::from(hash)).map(|code| {
+            let start = (from_index.0 as usize).min(code.len());
+            let end = start.saturating_add(limit as usize).min(code.len());
+
+            code[start..end].to_vec().into()
+        })
+    }
+
+    // Returns a proposal that can still be approved or deployed
+    fn internal_get_proposal(
+        &mut self,
+        hash: &Base58CryptoHash,
+    ) -> &mut UpgradeProposal {
+        let proposal = self
+            .upgrade_proposals
+            .get_mut(&CryptoHash::from(*hash))
+            .unwrap_or_else(|| ContractError::ProposalNotFound.panic());
+
+        ensure!(
+            env::block_timestamp() < proposal.expires_at.0,
+            ContractError::ProposalExpired
+        );
+
+        proposal
+    }
+
+    fn internal_remove_staged_code(&mut self, hash: &CryptoHash) -> Vec<u8> {
+        let initial_storage = env::storage_usage();
+
+        let proposal = self
+            .upgrade_proposals
+            .remove(hash)
+            .unwrap_or_else(|| ContractError::ProposalNotFound.panic());
+        let code = self
+            .staged_code
+            .remove(hash)
+            .unwrap_or_else(|| ContractError::ProposalNotFound.panic());
+
+        self.upgrade_proposals.flush();
+        self.staged_code.flush();
+
+        let freed = env::storage_byte_cost()
+            .saturating_mul((initial_storage - env::storage_usage()).into());
+
+        if !freed.is_zero() {
+            Promise::new(proposal.proposer).transfer(freed);
+        }
+
+        code
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use near_sdk::{test_utils::VMContextBuilder, testing_env, PromiseResult};
+
+    use super::*;
+    use crate::{
+        test_utils::{
+            context, execute, new_contract, new_contract_without_timelock,
+            set_context_at,
+        },
+        timelock::Action,
+    };
+
+    const CODE: &[u8] = b"\0asm";
+
+    #[test]
+    fn stage_and_approve_code() {
+        let mut contract = new_contract_without_timelock();
+
+        set_context("manager.near", NearToken::from_near(0));
+        execute(&mut contract, Action::SetUpgradeThreshold { threshold: 2 });
+        execute(
+            &mut contract,
+            Action::GrantRole {
+                account_id: "upgrader.near".parse().unwrap(),
+                role: Role::Upgrader,
+            },
+        );
+
+        set_context("manager.near", NearToken::from_near(1));
+        let hash = contract.stage_code();
+
+        let staged_code = contract.get_staged_code(hash).unwrap();
+        assert_eq!(staged_code.length.0, CODE.len() as u64);
+
+        let chunk = contract.get_staged_code_chunk(hash, U64(1), Some(2));
+        assert_eq!(Vec::from(chunk.unwrap()), &CODE[1..3]);
+
+        let chunk = contract.get_staged_code_chunk(hash, U64(0), None);
+        assert_eq!(Vec::from(chunk.unwrap()), CODE);
+
+        set_context("upgrader.near", NearToken::from_near(0));
+        contract.approve_code(hash);
+
+        let proposal = contract.get_upgrade_proposal(hash).unwrap();
+        assert_eq!(proposal.approvals.len(), 2);
+        assert_eq!(proposal.approvals[1].as_str(), "upgrader.near");
+
+        contract.deploy_staged(hash);
+
+        // the code is only removed once the migration succeeded
+        assert!(contract.get_staged_code(hash).is_some());
+
+        set_promise_result(PromiseResult::Successful(vec![]));
+        assert!(contract.resolve_deploy_staged(hash));
+
+        assert!(contract.get_upgrade_proposal(hash).is_none());
+        assert!(contract.get_staged_code(hash).is_none());
+
+        let history = contract.get_upgrade_history(None, None);
+        assert_eq!(history.len(), 1);
+        assert!(history[0].success);
+        assert_eq!(history[0].version, contract.get_version());
+    }
+
+    #[test]
+    fn resolve_deploy_staged_failed() {
+        let mut contract = new_contract_without_timelock();
+
+        set_context("manager.near", NearToken::from_near(1));
+        let hash = contract.stage_code();
+
+        contract.deploy_staged(hash);
+
+        set_promise_result(PromiseResult::Failed);
+        assert!(!contract.resolve_deploy_staged(hash));
+
+        // the code can be deployed again
+        assert!(contract.get_staged_code(hash).is_some());
+        assert!(!contract.get_upgrade_history(None, None)[0].success);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_NOT_ENOUGH_APPROVALS")]
+    fn deploy_staged_not_enough_approvals() {
+        let mut contract = new_contract_without_timelock();
+
+        set_context("manager.near", NearToken::from_near(0));
+        execute(&mut contract, Action::SetUpgradeThreshold { threshold: 2 });
+
+        set_context("manager.near", NearToken::from_near(1));
+        let hash = contract.stage_code();
+
+        contract.deploy_staged(hash);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_TIMELOCKED")]
+    fn deploy_staged_timelocked() {
+        let mut contract = new_contract();
+
+        set_context("manager.near", NearToken::from_near(1));
+        let hash = contract.stage_code();
+
+        contract.deploy_staged(hash);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_PROPOSAL_EXPIRED")]
+    fn approve_expired_code() {
+        let mut contract = new_contract_without_timelock();
+
+        set_context("manager.near", NearToken::from_near(0));
+        execute(
+            &mut contract,
+            Action::GrantRole {
+                account_id: "upgrader.near".parse().unwrap(),
+                role: Role::Upgrader,
+            },
+        );
+
+        set_context("manager.near", NearToken::from_near(1));
+        let hash = contract.stage_code();
+
+        set_context_at(
+            "upgrader.near",
+            NearToken::from_near(0),
+            DEFAULT_UPGRADE_TTL,
+        );
+
+        contract.approve_code(hash);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_ALREADY_APPROVED")]
+    fn approve_code_twice() {
+        let mut contract = new_contract_without_timelock();
+
+        set_context("manager.near", NearToken::from_near(1));
+        let hash = contract.stage_code();
+
+        contract.approve_code(hash);
+    }
+
+    fn set_promise_result(result: PromiseResult) {
+        testing_env!(
+            VMContextBuilder::new().build(),
+            near_sdk::test_vm_config(),
+            near_sdk::RuntimeFeesConfig::test(),
+            Default::default(),
+            vec![result],
+        );
+    }
+
+    // Staged code is read from the input of the call
+    fn set_context(predecessor: &str, amount: NearToken) {
+        testing_env!(context(predecessor, amount).input(CODE.to_vec()).build());
+    }
+}
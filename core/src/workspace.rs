@@ -0,0 +1,924 @@
+use near_sdk::{
+    assert_one_yocto, env,
+    json_types::{U128, U64},
+    near,
+    store::{IterableMap, IterableSet},
+    AccountId, FunctionError, NearToken, Promise,
+};
+
+use crate::{
+    errors::{ensure, ContractError},
+    events::{
+        MemberInvited, MemberJoined, MemberRemoved, MemberRoleChanged,
+        NoteAdded, NoteRemoved, NoteUpdated, NotebookEvent, WorkspaceCreated,
+        WorkspaceDeleted,
+    },
+    history::NoteRevision,
+    pausable::{Feature, Pausable},
+    storage::StorageAccount,
+    tags::{assert_valid_tags, diff_tags},
+    Contract, ContractExt, PostedNote, StorageKey,
+};
+
+pub(crate) const MAX_WORKSPACE_NAME_LENGTH: usize = 64;
+
+// Roles within a workspace, each one including the ones before it
+#[near(serializers = [borsh, json])]
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum WorkspaceRole {
+    Member,
+    Admin,
+    Owner,
+}
+
+// A note book owned by a group of accounts. Its notes are paid for from its
+// own storage balance, which anyone can top up
+#[near(serializers = [borsh])]
+pub struct Workspace {
+    pub name: String,
+    pub owner: AccountId,
+    pub members: IterableMap<AccountId, WorkspaceRole>,
+    // Accounts invited to join, until they accept
+    pub invites: IterableSet<AccountId>,
+    pub note_ids: IterableSet<u64>,
+    pub storage: StorageAccount,
+}
+
+impl Workspace {
+    // Panics unless the caller holds at least `role` in the workspace
+    fn assert_role(&self, role: WorkspaceRole) {
+        let member_role = self
+            .members
+            .get(&env::predecessor_account_id())
+            .unwrap_or_else(|| ContractError::NotMember.panic());
+
+        ensure!(*member_role >= role, ContractError::MissingRole);
+    }
+}
+
+#[near(serializers = [json])]
+pub struct WorkspaceView {
+    pub id: U64,
+    pub name: String,
+    pub owner: AccountId,
+    pub storage_balance: U128,
+    pub members: u32,
+    pub notes: u32,
+}
+
+#[near(serializers = [json])]
+pub struct WorkspaceMember {
+    pub account_id: AccountId,
+    pub role: WorkspaceRole,
+}
+
+#[near]
+impl Contract {
+    // The attached deposit pays for the workspace, and whatever is left
+    // becomes its storage balance
+    #[payable]
+    pub fn create_workspace(&mut self, name: String) -> U64 {
+        self.assert_feature_not_paused(Feature::Notes);
+
+        ensure!(
+            !name.is_empty() && name.len() <= MAX_WORKSPACE_NAME_LENGTH,
+            ContractError::InvalidName
+        );
+
+        let account_id = env::predecessor_account_id();
+        let id = self.next_workspace_id;
+
+        let storage_usage = env::storage_usage();
+
+        let mut members = IterableMap::new(StorageKey::WorkspaceMembers(id));
+        members.insert(account_id.clone(), WorkspaceRole::Owner);
+        members.flush();
+
+        self.workspaces.insert(
+            id,
+            Workspace {
+                name: name.clone(),
+                owner: account_id.clone(),
+                members,
+                invites: IterableSet::new(StorageKey::WorkspaceInvites(id)),
+                note_ids: IterableSet::new(StorageKey::WorkspaceNoteIds(id)),
+                storage: StorageAccount::default(),
+            },
+        );
+        self.workspaces.flush();
+
+        let storage_cost = env::storage_byte_cost().as_yoctonear()
+            * (env::storage_usage() - storage_usage) as u128;
+        let deposit = env::attached_deposit().as_yoctonear();
+
+        ensure!(deposit >= storage_cost, ContractError::NotEnoughDeposit);
+
+        let workspace = self.internal_get_workspace_mut(id);
+        workspace.storage = StorageAccount {
+            total: deposit,
+            available: deposit - storage_cost,
+        };
+
+        self.next_workspace_id += 1;
+
+        NotebookEvent::WorkspaceCreated(WorkspaceCreated {
+            account_id,
+            workspace_id: U64(id),
+            name,
+        })
+        .emit();
+
+        U64(id)
+    }
+
+    #[payable]
+    pub fn workspace_storage_deposit(&mut self, workspace_id: U64) -> U128 {
+        let amount = env::attached_deposit().as_yoctonear();
+
+        let workspace = self.internal_get_workspace_mut(workspace_id.0);
+
+        workspace.storage.total += amount;
+        workspace.storage.available += amount;
+
+        U128(workspace.storage.available)
+    }
+
+    // Only the owner can take back what the workspace doesn't use for storage
+    #[payable]
+    pub fn workspace_storage_withdraw(
+        &mut self,
+        workspace_id: U64,
+        amount: Option<NearToken>,
+    ) -> U128 {
+        assert_one_yocto();
+
+        let workspace = self.internal_get_workspace_mut(workspace_id.0);
+
+        workspace.assert_role(WorkspaceRole::Owner);
+
+        let amount = amount.map_or(workspace.storage.available, |amount| {
+            amount.as_yoctonear()
+        });
+
+        ensure!(
+            amount <= workspace.storage.available,
+            ContractError::NotEnoughBalance
+        );
+
+        workspace.storage.total -= amount;
+        workspace.storage.available -= amount;
+
+        let available = workspace.storage.available;
+
+        if amount != 0 {
+            Promise::new(env::predecessor_account_id())
+                .transfer(NearToken::from_yoctonear(amount));
+        }
+
+        U128(available)
+    }
+
+    // The owner deletes a workspace once its notes are removed, and gets back
+    // its storage balance along with the storage it frees
+    #[payable]
+    pub fn delete_workspace(&mut self, workspace_id: U64) {
+        assert_one_yocto();
+
+        let account_id = env::predecessor_account_id();
+
+        let storage_usage = env::storage_usage();
+
+        let mut workspace = self
+            .workspaces
+            .remove(&workspace_id.0)
+            .unwrap_or_else(|| ContractError::WorkspaceNotFound.panic());
+
+        workspace.assert_role(WorkspaceRole::Owner);
+
+        ensure!(workspace.note_ids.is_empty(), ContractError::HasNotes);
+
+        workspace.members.clear();
+        workspace.invites.clear();
+
+        workspace.members.flush();
+        workspace.invites.flush();
+        self.workspaces.flush();
+
+        let to_refund = workspace.storage.available
+            + env::storage_byte_cost().as_yoctonear()
+                * storage_usage.saturating_sub(env::storage_usage()) as u128;
+
+        Promise::new(account_id.clone())
+            .transfer(NearToken::from_yoctonear(to_refund));
+
+        NotebookEvent::WorkspaceDeleted(WorkspaceDeleted {
+            account_id,
+            workspace_id,
+        })
+        .emit();
+    }
+
+    // Admins invite accounts, which become members once they accept
+    pub fn invite_member(&mut self, workspace_id: U64, account_id: AccountId) {
+        let storage_usage = env::storage_usage();
+
+        let workspace = self.internal_get_workspace_mut(workspace_id.0);
+
+        workspace.assert_role(WorkspaceRole::Admin);
+
+        ensure!(
+            !workspace.members.contains_key(&account_id),
+            ContractError::AlreadyMember
+        );
+        ensure!(
+            workspace.invites.insert(account_id.clone()),
+            ContractError::AlreadyInvited
+        );
+
+        workspace.invites.flush();
+
+        self.internal_settle_workspace_storage(workspace_id.0, storage_usage);
+
+        NotebookEvent::MemberInvited(MemberInvited {
+            account_id: env::predecessor_account_id(),
+            workspace_id,
+            invitee: account_id,
+        })
+        .emit();
+    }
+
+    pub fn accept_invite(&mut self, workspace_id: U64) {
+        let account_id = env::predecessor_account_id();
+
+        let storage_usage = env::storage_usage();
+
+        let workspace = self.internal_get_workspace_mut(workspace_id.0);
+
+        ensure!(
+            workspace.invites.remove(&account_id),
+            ContractError::NotInvited
+        );
+
+        workspace
+            .members
+            .insert(account_id.clone(), WorkspaceRole::Member);
+
+        workspace.invites.flush();
+        workspace.members.flush();
+
+        self.internal_settle_workspace_storage(workspace_id.0, storage_usage);
+
+        NotebookEvent::MemberJoined(MemberJoined {
+            account_id,
+            workspace_id,
+        })
+        .emit();
+    }
+
+    // Members can leave on their own, admins can remove members and the owner
+    // can remove admins. The owner always stays
+    pub fn remove_member(&mut self, workspace_id: U64, account_id: AccountId) {
+        let storage_usage = env::storage_usage();
+
+        let workspace = self.internal_get_workspace_mut(workspace_id.0);
+
+        let role = *workspace
+            .members
+            .get(&account_id)
+            .unwrap_or_else(|| ContractError::NotMember.panic());
+
+        ensure!(role != WorkspaceRole::Owner, ContractError::Owner);
+
+        if account_id != env::predecessor_account_id() {
+            workspace.assert_role(match role {
+                WorkspaceRole::Member => WorkspaceRole::Admin,
+                _ => WorkspaceRole::Owner,
+            });
+        }
+
+        workspace.members.remove(&account_id);
+        workspace.members.flush();
+
+        self.internal_settle_workspace_storage(workspace_id.0, storage_usage);
+
+        NotebookEvent::MemberRemoved(MemberRemoved {
+            account_id: env::predecessor_account_id(),
+            workspace_id,
+            member: account_id,
+        })
+        .emit();
+    }
+
+    // Only the owner promotes members to admins or demotes them back
+    pub fn set_member_role(
+        &mut self,
+        workspace_id: U64,
+        account_id: AccountId,
+        role: WorkspaceRole,
+    ) {
+        ensure!(role != WorkspaceRole::Owner, ContractError::Owner);
+
+        let workspace = self.internal_get_workspace_mut(workspace_id.0);
+
+        workspace.assert_role(WorkspaceRole::Owner);
+
+        let member_role = workspace
+            .members
+            .get_mut(&account_id)
+            .unwrap_or_else(|| ContractError::NotMember.panic());
+
+        ensure!(*member_role != WorkspaceRole::Owner, ContractError::Owner);
+
+        *member_role = role;
+
+        NotebookEvent::MemberRoleChanged(MemberRoleChanged {
+            account_id: env::predecessor_account_id(),
+            workspace_id,
+            member: account_id,
+            role,
+        })
+        .emit();
+    }
+
+    pub fn add_workspace_note(
+        &mut self,
+        workspace_id: U64,
+        title: String,
+        body: String,
+        tags: Option<Vec<String>>,
+    ) -> U64 {
+        self.assert_feature_not_paused(Feature::Notes);
+        self.assert_not_migrating();
+
+        self.note_limits.assert_valid_note(&title, &body, None);
+
+        let tags = tags.unwrap_or_default();
+        assert_valid_tags(&tags);
+
+        let account_id = env::predecessor_account_id();
+        let id = self.internal_next_note_id();
+
+        let storage_usage = env::storage_usage();
+
+        let workspace = self.internal_get_workspace_mut(workspace_id.0);
+
+        workspace.assert_role(WorkspaceRole::Member);
+
+        workspace.note_ids.insert(id);
+        workspace.note_ids.flush();
+
+        let note = PostedNote::new(
+            title,
+            body,
+            Some(U64(id)),
+            account_id.clone(),
+            tags.clone(),
+        );
+
+        NotebookEvent::NoteAdded(NoteAdded {
+            account_id,
+            id: U64(id),
+            title: note.title.clone(),
+            workspace_id: Some(workspace_id),
+        })
+        .emit();
+
+        self.workspace_notes.insert((workspace_id.0, id), note);
+        self.workspace_notes.flush();
+
+        self.internal_tag_workspace_note(workspace_id.0, id, &tags);
+
+        self.internal_settle_workspace_storage(workspace_id.0, storage_usage);
+
+        U64(id)
+    }
+
+    // Any member can edit a workspace note. Its tags are kept when `tags` is
+    // left out
+    pub fn update_workspace_note(
+        &mut self,
+        workspace_id: U64,
+        id: U64,
+        title: String,
+        body: String,
+        tags: Option<Vec<String>>,
+    ) {
+        self.assert_feature_not_paused(Feature::Notes);
+
+        if let Some(tags) = &tags {
+            assert_valid_tags(tags);
+        }
+
+        self.internal_update_workspace_note(
+            workspace_id,
+            id,
+            title,
+            body,
+            tags,
+        );
+    }
+
+    // Like `restore_note_revision`, any member can restore a revision of a
+    // workspace note
+    pub fn restore_workspace_note_revision(
+        &mut self,
+        workspace_id: U64,
+        id: U64,
+        revision: u32,
+    ) {
+        self.assert_feature_not_paused(Feature::Notes);
+
+        let NoteRevision { title, body, .. } = self
+            .workspace_note_revisions
+            .get(&(workspace_id.0, id.0))
+            .and_then(|revisions| revisions.get(revision as usize))
+            .cloned()
+            .unwrap_or_else(|| ContractError::RevisionNotFound.panic());
+
+        self.internal_update_workspace_note(
+            workspace_id,
+            id,
+            title,
+            body,
+            None,
+        );
+    }
+
+    // The author of a workspace note, or an admin, can remove it
+    pub fn remove_workspace_note(&mut self, workspace_id: U64, id: U64) {
+        let storage_usage = env::storage_usage();
+
+        let note = self
+            .workspace_notes
+            .remove(&(workspace_id.0, id.0))
+            .unwrap_or_else(|| ContractError::NoteNotFound.panic());
+
+        let workspace = self.internal_get_workspace_mut(workspace_id.0);
+
+        if note.author != env::predecessor_account_id() {
+            workspace.assert_role(WorkspaceRole::Admin);
+        }
+
+        workspace.note_ids.remove(&id.0);
+        workspace.note_ids.flush();
+
+        self.workspace_notes.flush();
+
+        self.workspace_note_revisions
+            .remove(&(workspace_id.0, id.0));
+        self.workspace_note_revisions.flush();
+
+        self.internal_untag_workspace_note(workspace_id.0, id.0, &note.tags);
+
+        self.internal_settle_workspace_storage(workspace_id.0, storage_usage);
+
+        NotebookEvent::NoteRemoved(NoteRemoved {
+            account_id: env::predecessor_account_id(),
+            id,
+            workspace_id: Some(workspace_id),
+        })
+        .emit();
+    }
+
+    pub fn get_workspace(&self, workspace_id: U64) -> WorkspaceView {
+        let workspace = self.internal_get_workspace(workspace_id.0);
+
+        WorkspaceView {
+            id: workspace_id,
+            name: workspace.name.clone(),
+            owner: workspace.owner.clone(),
+            storage_balance: U128(workspace.storage.available),
+            members: workspace.members.len(),
+            notes: workspace.note_ids.len(),
+        }
+    }
+
+    pub fn get_workspace_members(
+        &self,
+        workspace_id: U64,
+        from_index: Option<u32>,
+        limit: Option<u32>,
+    ) -> Vec<WorkspaceMember> {
+        self.internal_get_workspace(workspace_id.0)
+            .members
+            .iter()
+            .skip(from_index.unwrap_or(0) as usize)
+            .take(limit.unwrap_or(u32::MAX) as usize)
+            .map(|(account_id, role)| WorkspaceMember {
+                account_id: account_id.clone(),
+                role: *role,
+            })
+            .collect()
+    }
+
+    pub fn get_workspace_invites(&self, workspace_id: U64) -> Vec<&AccountId> {
+        self.internal_get_workspace(workspace_id.0)
+            .invites
+            .iter()
+            .collect()
+    }
+
+    pub fn get_workspace_note(
+        &self,
+        workspace_id: U64,
+        id: U64,
+    ) -> &PostedNote {
+        self.workspace_notes
+            .get(&(workspace_id.0, id.0))
+            .unwrap_or_else(|| ContractError::NoteNotFound.panic())
+    }
+
+    // Previous revisions of a workspace note, oldest first
+    pub fn get_workspace_note_history(
+        &self,
+        workspace_id: U64,
+        id: U64,
+    ) -> Vec<&NoteRevision> {
+        self.workspace_note_revisions
+            .get(&(workspace_id.0, id.0))
+            .map_or(vec![], |revisions| revisions.iter().collect())
+    }
+
+    pub fn get_workspace_notes(
+        &self,
+        workspace_id: U64,
+        from_index: Option<u32>,
+        limit: Option<u32>,
+    ) -> Vec<&PostedNote> {
+        self.internal_get_workspace(workspace_id.0)
+            .note_ids
+            .iter()
+            .skip(from_index.unwrap_or(0) as usize)
+            .take(limit.unwrap_or(u32::MAX) as usize)
+            .map(|id| {
+                self.workspace_notes
+                    .get(&(workspace_id.0, *id))
+                    .unwrap_or_else(|| ContractError::NoteNotFound.panic())
+            })
+            .collect()
+    }
+
+    // Edits a workspace note, keeping its previous content as a revision paid
+    // for by the workspace
+    fn internal_update_workspace_note(
+        &mut self,
+        workspace_id: U64,
+        id: U64,
+        title: String,
+        body: String,
+        tags: Option<Vec<String>>,
+    ) {
+        self.note_limits.assert_valid_note(&title, &body, None);
+
+        self.internal_get_workspace(workspace_id.0)
+            .assert_role(WorkspaceRole::Member);
+
+        let storage_usage = env::storage_usage();
+
+        let note = self
+            .workspace_notes
+            .get_mut(&(workspace_id.0, id.0))
+            .unwrap_or_else(|| ContractError::NoteNotFound.panic());
+
+        let revision = NoteRevision {
+            title: std::mem::replace(&mut note.title, title),
+            body: std::mem::replace(&mut note.body, body),
+            encrypted: None,
+            content_ref: None,
+            editor: env::predecessor_account_id(),
+            timestamp: U64(env::block_timestamp()),
+        };
+
+        note.updated_at = U64(env::block_timestamp());
+
+        let old_tags = tags.map(|tags| std::mem::replace(&mut note.tags, tags));
+        let new_tags = note.tags.clone();
+
+        NotebookEvent::NoteUpdated(NoteUpdated {
+            account_id: env::predecessor_account_id(),
+            id,
+            title: note.title.clone(),
+            workspace_id: Some(workspace_id),
+        })
+        .emit();
+
+        if let Some(old_tags) = old_tags {
+            let (removed, added) = diff_tags(old_tags, &new_tags);
+
+            self.internal_untag_workspace_note(workspace_id.0, id.0, &removed);
+            self.internal_tag_workspace_note(workspace_id.0, id.0, &added);
+        }
+
+        self.internal_push_workspace_revision(workspace_id.0, id.0, revision);
+
+        self.workspace_notes.flush();
+
+        self.internal_settle_workspace_storage(workspace_id.0, storage_usage);
+    }
+
+    fn internal_get_workspace(&self, workspace_id: u64) -> &Workspace {
+        self.workspaces
+            .get(&workspace_id)
+            .unwrap_or_else(|| ContractError::WorkspaceNotFound.panic())
+    }
+
+    fn internal_get_workspace_mut(
+        &mut self,
+        workspace_id: u64,
+    ) -> &mut Workspace {
+        self.workspaces
+            .get_mut(&workspace_id)
+            .unwrap_or_else(|| ContractError::WorkspaceNotFound.panic())
+    }
+
+    // Like `internal_settle_storage`, but against the storage balance of the
+    // workspace
+    fn internal_settle_workspace_storage(
+        &mut self,
+        workspace_id: u64,
+        initial_storage: u64,
+    ) {
+        self.workspaces.flush();
+
+        let storage_usage = env::storage_usage();
+        let byte_cost = env::storage_byte_cost().as_yoctonear();
+
+        let workspace = self.internal_get_workspace_mut(workspace_id);
+
+        if storage_usage > initial_storage {
+            let storage_cost =
+                byte_cost * (storage_usage - initial_storage) as u128;
+
+            workspace.storage.available = workspace
+                .storage
+                .available
+                .checked_sub(storage_cost)
+                .unwrap_or_else(|| ContractError::NotEnoughBalance.panic());
+        } else {
+            workspace.storage.available +=
+                byte_cost * (initial_storage - storage_usage) as u128;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use near_sdk::NearToken;
+
+    use super::*;
+    use crate::test_utils::{new_contract, set_context};
+
+    #[test]
+    fn invite_and_accept() {
+        let mut contract = new_contract();
+
+        set_context("owner.near", NearToken::from_near(1));
+        let id = contract.create_workspace("team".into());
+
+        contract.invite_member(id, "account_id1".parse().unwrap());
+        assert_eq!(contract.get_workspace_invites(id).len(), 1);
+
+        set_context("account_id1", NearToken::from_near(0));
+        contract.accept_invite(id);
+
+        let members = contract.get_workspace_members(id, None, None);
+        assert_eq!(members.len(), 2);
+        assert!(members[1].role == WorkspaceRole::Member);
+        assert!(contract.get_workspace_invites(id).is_empty());
+
+        // members can leave on their own
+        contract.remove_member(id, "account_id1".parse().unwrap());
+        assert_eq!(contract.get_workspace(id).members, 1);
+    }
+
+    #[test]
+    fn workspace_notes() {
+        let mut contract = new_contract();
+
+        set_context("owner.near", NearToken::from_near(1));
+        let id = contract.create_workspace("team".into());
+
+        let balance = contract.get_workspace(id).storage_balance;
+
+        contract.add_workspace_note(id, "title".into(), "body".into(), None);
+        contract.add_workspace_note(id, "title2".into(), "body2".into(), None);
+
+        // notes are paid for by the workspace
+        assert!(contract.get_workspace(id).storage_balance.0 < balance.0);
+
+        let notes = contract.get_workspace_notes(id, Some(1), Some(1));
+        assert_eq!(notes.len(), 1);
+        assert_eq!(notes[0].title, "title2");
+        assert_eq!(notes[0].author.as_str(), "owner.near");
+
+        contract.update_workspace_note(
+            id,
+            U64(0),
+            "new title".into(),
+            "body".into(),
+            None,
+        );
+        assert_eq!(contract.get_workspace_note(id, U64(0)).title, "new title");
+
+        // revisions are paid for by the workspace as well
+        let history = contract.get_workspace_note_history(id, U64(0));
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].title, "title");
+        assert_eq!(history[0].editor.as_str(), "owner.near");
+
+        contract.remove_workspace_note(id, U64(0));
+        contract.remove_workspace_note(id, U64(1));
+
+        assert_eq!(contract.get_workspace(id).notes, 0);
+        assert_eq!(contract.get_workspace(id).storage_balance, balance);
+    }
+
+    #[test]
+    fn workspace_storage_withdraw() {
+        let mut contract = new_contract();
+
+        set_context("owner.near", NearToken::from_near(1));
+        let id = contract.create_workspace("team".into());
+
+        let balance = contract.get_workspace(id).storage_balance;
+
+        set_context("owner.near", NearToken::from_yoctonear(1));
+
+        let available = contract.workspace_storage_withdraw(
+            id,
+            Some(NearToken::from_yoctonear(10)),
+        );
+        assert_eq!(available.0, balance.0 - 10);
+
+        let available = contract.workspace_storage_withdraw(id, None);
+        assert_eq!(available.0, 0);
+        assert_eq!(contract.get_workspace(id).storage_balance.0, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_MISSING_ROLE")]
+    fn workspace_storage_withdraw_not_owner() {
+        let mut contract = new_contract();
+
+        set_context("owner.near", NearToken::from_near(1));
+        let id = contract.create_workspace("team".into());
+
+        contract.invite_member(id, "account_id1".parse().unwrap());
+
+        set_context("account_id1", NearToken::from_near(0));
+        contract.accept_invite(id);
+
+        set_context("account_id1", NearToken::from_yoctonear(1));
+        contract.workspace_storage_withdraw(id, None);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_WORKSPACE_NOT_FOUND")]
+    fn delete_workspace() {
+        let mut contract = new_contract();
+
+        set_context("owner.near", NearToken::from_near(1));
+        let id = contract.create_workspace("team".into());
+
+        contract.invite_member(id, "account_id1".parse().unwrap());
+        let note_id = contract.add_workspace_note(
+            id,
+            "title".into(),
+            "body".into(),
+            None,
+        );
+        contract.remove_workspace_note(id, note_id);
+
+        set_context("owner.near", NearToken::from_yoctonear(1));
+        contract.delete_workspace(id);
+
+        contract.get_workspace(id);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_HAS_NOTES")]
+    fn delete_workspace_with_notes() {
+        let mut contract = new_contract();
+
+        set_context("owner.near", NearToken::from_near(1));
+        let id = contract.create_workspace("team".into());
+
+        contract.add_workspace_note(id, "title".into(), "body".into(), None);
+
+        set_context("owner.near", NearToken::from_yoctonear(1));
+        contract.delete_workspace(id);
+    }
+
+    #[test]
+    fn restore_workspace_note_revision() {
+        let mut contract = new_contract();
+
+        set_context("owner.near", NearToken::from_near(1));
+        let id = contract.create_workspace("team".into());
+
+        contract.invite_member(id, "account_id1".parse().unwrap());
+        let note_id = contract.add_workspace_note(
+            id,
+            "title".into(),
+            "body".into(),
+            None,
+        );
+
+        set_context("account_id1", NearToken::from_near(0));
+        contract.accept_invite(id);
+        contract.update_workspace_note(
+            id,
+            note_id,
+            "new title".into(),
+            "new body".into(),
+            None,
+        );
+
+        // restoring is an edit too, and keeps the replaced content
+        contract.restore_workspace_note_revision(id, note_id, 0);
+
+        let note = contract.get_workspace_note(id, note_id);
+        assert_eq!(note.title, "title");
+        assert_eq!(note.body, "body");
+
+        let history = contract.get_workspace_note_history(id, note_id);
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[1].title, "new title");
+        assert_eq!(history[1].editor.as_str(), "account_id1");
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_NOT_MEMBER")]
+    fn restore_workspace_note_revision_not_member() {
+        let mut contract = new_contract();
+
+        set_context("owner.near", NearToken::from_near(1));
+        let id = contract.create_workspace("team".into());
+
+        let note_id = contract.add_workspace_note(
+            id,
+            "title".into(),
+            "body".into(),
+            None,
+        );
+        contract.update_workspace_note(
+            id,
+            note_id,
+            "new title".into(),
+            "body".into(),
+            None,
+        );
+
+        set_context("account_id1", NearToken::from_near(0));
+        contract.restore_workspace_note_revision(id, note_id, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_NOTE_NOT_FOUND")]
+    fn get_workspace_note_by_id() {
+        let mut contract = new_contract();
+
+        set_context("owner.near", NearToken::from_near(1));
+        let id = contract.create_workspace("team".into());
+
+        let note_id = contract.add_workspace_note(
+            id,
+            "title".into(),
+            "body".into(),
+            None,
+        );
+
+        // only personal notes can be looked up by id alone
+        contract.get_note_by_id(note_id);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_NOT_MEMBER")]
+    fn add_workspace_note_not_member() {
+        let mut contract = new_contract();
+
+        set_context("owner.near", NearToken::from_near(1));
+        let id = contract.create_workspace("team".into());
+
+        contract.invite_member(id, "account_id1".parse().unwrap());
+
+        // invited accounts have to accept first
+        set_context("account_id1", NearToken::from_near(0));
+        contract.add_workspace_note(id, "title".into(), "body".into(), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_MISSING_ROLE")]
+    fn invite_member_not_admin() {
+        let mut contract = new_contract();
+
+        set_context("owner.near", NearToken::from_near(1));
+        let id = contract.create_workspace("team".into());
+
+        contract.invite_member(id, "account_id1".parse().unwrap());
+
+        set_context("account_id1", NearToken::from_near(0));
+        contract.accept_invite(id);
+        contract.invite_member(id, "account_id2".parse().unwrap());
+    }
+}
@@ -1,11 +1,13 @@
 use near_sdk::{
-    json_types::{U128, U64},
+    json_types::{Base58CryptoHash, Base64VecU8, U128, U64},
     near,
     serde_json::json,
     AccountId,
 };
 use near_workspaces::{
-    result::ValueOrReceiptId, types::NearToken, Account, Contract,
+    result::{ExecutionFinalResult, ValueOrReceiptId},
+    types::NearToken,
+    Account, Contract,
 };
 
 #[near(serializers = [borsh, json])]
@@ -23,6 +25,8 @@ pub struct PostedNoteNew {
     pub title: String,
     pub body: String,
     pub author: AccountId,
+    pub created_at: U64,
+    pub updated_at: U64,
 }
 
 #[near(serializers = [json])]
@@ -52,6 +56,42 @@ const NOTE_BOOK_CONTRACT_VERSIONED: &[u8] =
 
 const FT_CONTRACT: &[u8] = include_bytes!("../../res/fungible_token.wasm");
 
+// Failed calls carry the code of the `ContractError` they failed with
+fn assert_failure(res: &ExecutionFinalResult, code: &str) {
+    assert!(res.is_failure());
+    assert!(
+        format!("{:?}", res.failures()).contains(code),
+        "expected {code}"
+    );
+}
+
+fn is_exchange_successful(log: &str) -> bool {
+    log.contains(r#""event":"exchange_resolved""#)
+        && log.contains(r#""success":true"#)
+}
+
+// Queues an action and executes it, the timelock delay being zero in tests
+async fn execute_action(
+    account: &Account,
+    contract: &Contract,
+    action: serde_json::Value,
+) -> color_eyre::Result<ExecutionFinalResult> {
+    let id = account
+        .call(contract.id(), "queue_action")
+        .args_json(json!({"action": action}))
+        .transact()
+        .await?
+        .into_result()?
+        .json::<U64>()?;
+
+    Ok(account
+        .call(contract.id(), "execute_action")
+        .args_json(json!({"id": id}))
+        .max_gas()
+        .transact()
+        .await?)
+}
+
 async fn prepare() -> color_eyre::Result<Env> {
     let sandbox = near_workspaces::sandbox().await?;
     let mainnet = near_workspaces::mainnet_archival().await?;
@@ -125,8 +165,9 @@ async fn prepare() -> color_eyre::Result<Env> {
 
     println!("fungible token deployed: {}\n", ft_contract.id());
 
+    // No timelock delay so that tests can execute actions right away
     note_book_contract.call("new")
-        .args_json(serde_json::json!({"managers": vec![manager.id()], "ft_id": ft_contract.id(), "w_near_id": w_near.id()}))
+        .args_json(serde_json::json!({"managers": vec![manager.id()], "ft_id": ft_contract.id(), "w_near_id": w_near.id(), "timelock_delay": "0"}))
         .transact().await?.into_result()?;
 
     println!("note book contract deployed: {}\n", note_book_contract.id());
@@ -180,9 +221,17 @@ async fn migration_works() -> color_eyre::Result<()> {
     let Env {
         note_book_contract_old,
         manager,
+        user,
         ..
     } = prepare().await?;
 
+    user.call(note_book_contract_old.id(), "add_note")
+        .deposit(NearToken::from_near(1))
+        .args_json(json!({"title": "Hello", "body": "World"}))
+        .transact()
+        .await?
+        .into_result()?;
+
     manager
         .call(note_book_contract_old.id(), "update_contract")
         .args(NOTE_BOOK_CONTRACT.to_vec())
@@ -197,14 +246,134 @@ async fn migration_works() -> color_eyre::Result<()> {
         .await?
         .json::<U64>()?;
 
-    assert_eq!(version.0, 2);
+    assert_eq!(version.0, 3);
+
+    // a single note fits into the batch run by `migrate`
+    let progress = note_book_contract_old
+        .view("get_migration_progress")
+        .args_json(json!({}))
+        .await?
+        .json::<serde_json::Value>()?;
+
+    assert_eq!(progress["in_progress"], false);
+
+    let note = note_book_contract_old
+        .view("get_note")
+        .args_json(json!({"account_id": user.id(), "id": U64(0)}))
+        .await?
+        .json::<PostedNote>()?;
+
+    assert_eq!(note.title, "Hello");
+    assert_eq!(note.body, "World");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn migration_works_versioned() -> color_eyre::Result<()> {
+    let Env {
+        ft_contract,
+        w_near,
+        manager,
+        user,
+        ..
+    } = prepare().await?;
+
+    let note_book_contract_versioned = manager
+        .create_subaccount("versioned")
+        .initial_balance(NearToken::from_near(10))
+        .transact()
+        .await?
+        .into_result()?
+        .deploy(NOTE_BOOK_CONTRACT_VERSIONED)
+        .await?
+        .into_result()?;
+
+    note_book_contract_versioned
+        .call("new")
+        .args_json(json!({
+            "managers": vec![manager.id()],
+            "ft_id": ft_contract.id(),
+            "w_near_id": w_near.id(),
+        }))
+        .transact()
+        .await?
+        .into_result()?;
+
+    user.call(note_book_contract_versioned.id(), "add_note")
+        .deposit(NearToken::from_near(1))
+        .args_json(json!({"title": "Hello", "body": "World"}))
+        .transact()
+        .await?
+        .into_result()?;
+
+    manager
+        .call(note_book_contract_versioned.id(), "update_contract")
+        .args(NOTE_BOOK_CONTRACT.to_vec())
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+
+    let version = note_book_contract_versioned
+        .view("get_version")
+        .args_json(json!({}))
+        .await?
+        .json::<U64>()?;
+
+    assert_eq!(version.0, 3);
+
+    let note = note_book_contract_versioned
+        .view("get_note")
+        .args_json(json!({"account_id": user.id(), "id": U64(0)}))
+        .await?
+        .json::<PostedNote>()?;
+
+    assert_eq!(note.title, "Hello");
 
     Ok(())
 }
 
 #[tokio::test]
 async fn migration_works_author_field() -> color_eyre::Result<()> {
-    todo!("HOMEWORK");
+    let Env {
+        note_book_contract_old,
+        manager,
+        user,
+        ..
+    } = prepare().await?;
+
+    for account in [&user, &manager] {
+        account
+            .call(note_book_contract_old.id(), "add_note")
+            .deposit(NearToken::from_near(1))
+            .args_json(json!({"title": "Hello", "body": "World"}))
+            .transact()
+            .await?
+            .into_result()?;
+    }
+
+    manager
+        .call(note_book_contract_old.id(), "update_contract")
+        .args(NOTE_BOOK_CONTRACT.to_vec())
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+
+    // notes are backfilled with the account they were stored under
+    for (account, id) in [(&user, 0), (&manager, 1)] {
+        let note = note_book_contract_old
+            .view("get_note")
+            .args_json(json!({"account_id": account.id(), "id": U64(id)}))
+            .await?
+            .json::<PostedNoteNew>()?;
+
+        assert_eq!(&note.author, account.id());
+        assert_eq!(note.title, "Hello");
+        assert!(note.created_at.0 > 0);
+        assert_eq!(note.created_at, note.updated_at);
+    }
 
     Ok(())
 }
@@ -234,7 +403,7 @@ async fn add_note() -> color_eyre::Result<()> {
     assert!(res
         .logs()
         .iter()
-        .find(|log| log.contains("Added note to the note book"))
+        .find(|log| log.contains(r#""event":"note_added""#))
         .is_some());
 
     let notes = note_book_contract
@@ -250,39 +419,79 @@ async fn add_note() -> color_eyre::Result<()> {
 }
 
 #[tokio::test]
-async fn add_note_ft() -> color_eyre::Result<()> {
+async fn get_note_by_id() -> color_eyre::Result<()> {
     let Env {
         note_book_contract,
-        ft_contract,
         user,
         manager,
         ..
     } = prepare().await?;
 
-    manager
-        .call(ft_contract.id(), "ft_transfer")
-        .args_json(json!({
-            "receiver_id": user.id(),
-            "amount": U128(NearToken::from_near(2).as_yoctonear()),
-        }))
-        .deposit(NearToken::from_yoctonear(1))
+    for (account, title) in [(&user, "Hello"), (&manager, "Howdy")] {
+        account
+            .call(note_book_contract.id(), "add_note")
+            .deposit(NearToken::from_near(1))
+            .args_json(json!({"title": title, "body": "World"}))
+            .transact()
+            .await?
+            .into_result()?;
+    }
+
+    // ids are allocated from a single counter, whoever the author is
+    let note = note_book_contract
+        .view("get_note_by_id")
+        .args_json(json!({"id": U64(1)}))
+        .await?
+        .json::<PostedNote>()?;
+
+    assert_eq!(note.title, "Howdy");
+
+    let res = note_book_contract
+        .view("get_note")
+        .args_json(json!({"account_id": user.id(), "id": U64(2)}))
+        .await;
+
+    assert!(res.is_err());
+
+    let res = note_book_contract
+        .view("get_note_by_id")
+        .args_json(json!({"id": U64(2)}))
+        .await;
+
+    assert!(res.is_err());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn update_note() -> color_eyre::Result<()> {
+    let Env {
+        note_book_contract,
+        user,
+        manager,
+        ..
+    } = prepare().await?;
+
+    user.call(note_book_contract.id(), "add_note")
+        .deposit(NearToken::from_near(1))
+        .args_json(json!({"title": "Helo", "body": "World"}))
         .transact()
         .await?
         .into_result()?;
 
+    let res = manager
+        .call(note_book_contract.id(), "update_note")
+        .deposit(NearToken::from_near(1))
+        .args_json(json!({"id": U64(0), "title": "Hello", "body": "World"}))
+        .transact()
+        .await?;
+
+    assert_failure(&res, "ERR_NOTE_NOT_FOUND");
+
     let res = user
-        .call(ft_contract.id(), "ft_transfer_call")
-        .args_json(json!({
-            "receiver_id": note_book_contract.id(),
-            "amount": U128(NearToken::from_near(1).as_yoctonear()),
-            "msg": serde_json::to_string(&FtMessage::AddNote(PostedNote {
-                id: None,
-                title: "Hello".to_string(),
-                body: "World".to_string(),
-            }))?,
-        }))
-        .max_gas()
-        .deposit(NearToken::from_yoctonear(1))
+        .call(note_book_contract.id(), "update_note")
+        .deposit(NearToken::from_near(1))
+        .args_json(json!({"id": U64(0), "title": "Hello", "body": "World"}))
         .transact()
         .await?
         .into_result()?;
@@ -290,8 +499,7 @@ async fn add_note_ft() -> color_eyre::Result<()> {
     assert!(res
         .logs()
         .iter()
-        .find(|log| log.contains("Added note to the note book"))
-        .is_some());
+        .any(|log| log.contains(r#""event":"note_updated""#)));
 
     let notes = note_book_contract
         .view("get_notes")
@@ -300,36 +508,1022 @@ async fn add_note_ft() -> color_eyre::Result<()> {
         .json::<Vec<PostedNote>>()?;
 
     assert_eq!(notes.len(), 1);
+    assert_eq!(notes[0].id, Some(U64(0)));
     assert_eq!(notes[0].title, "Hello");
-    assert_eq!(notes[0].body, "World");
+    Ok(())
+}
 
-    let points = note_book_contract
-        .view("get_user_points")
+#[tokio::test]
+async fn notes_by_tag() -> color_eyre::Result<()> {
+    let Env {
+        note_book_contract,
+        user,
+        ..
+    } = prepare().await?;
+
+    for (title, tags) in [("a", vec!["work"]), ("b", vec!["work", "home"])] {
+        user.call(note_book_contract.id(), "add_note")
+            .deposit(NearToken::from_near(1))
+            .args_json(json!({"title": title, "body": "World", "tags": tags}))
+            .transact()
+            .await?
+            .into_result()?;
+    }
+
+    let notes = note_book_contract
+        .view("get_notes_by_tag")
+        .args_json(json!({"account_id": user.id(), "tag": "work"}))
+        .await?
+        .json::<Vec<PostedNote>>()?;
+
+    assert_eq!(notes.len(), 2);
+
+    user.call(note_book_contract.id(), "update_note")
+        .args_json(json!({
+            "id": U64(1),
+            "title": "b",
+            "body": "World",
+            "tags": ["home"],
+        }))
+        .transact()
+        .await?
+        .into_result()?;
+
+    let notes = note_book_contract
+        .view("get_notes_by_tag")
+        .args_json(json!({"account_id": user.id(), "tag": "work"}))
+        .await?
+        .json::<Vec<PostedNote>>()?;
+
+    assert_eq!(notes.len(), 1);
+    assert_eq!(notes[0].title, "a");
+
+    let mut tags = note_book_contract
+        .view("get_tags")
         .args_json(json!({"account_id": user.id()}))
         .await?
-        .json::<U128>()?;
+        .json::<Vec<String>>()?;
+    tags.sort();
 
-    assert_eq!(points.0, NearToken::from_near(1).as_yoctonear());
+    assert_eq!(tags, vec!["home", "work"]);
 
     Ok(())
 }
 
-async fn remove_note() -> color_eyre::Result<()> {
-    todo!("HOMEWORK");
-}
+#[tokio::test]
+async fn share_note() -> color_eyre::Result<()> {
+    let Env {
+        note_book_contract,
+        user,
+        manager,
+        ..
+    } = prepare().await?;
 
-async fn remove_note_ft() -> color_eyre::Result<()> {
-    todo!("HOMEWORK");
+    user.call(note_book_contract.id(), "add_note")
+        .deposit(NearToken::from_near(1))
+        .args_json(json!({"title": "Helo", "body": "World"}))
+        .transact()
+        .await?
+        .into_result()?;
+
+    let update = json!({
+        "id": U64(0),
+        "title": "Hello",
+        "body": "World",
+        "author": user.id(),
+    });
+
+    let res = manager
+        .call(note_book_contract.id(), "update_note")
+        .args_json(update.clone())
+        .transact()
+        .await?;
+
+    assert_failure(&res, "ERR_NO_ACCESS");
+
+    user.call(note_book_contract.id(), "grant_access")
+        .args_json(json!({
+            "grantee": manager.id(),
+            "id": U64(0),
+            "permission": "Write",
+        }))
+        .transact()
+        .await?
+        .into_result()?;
+
+    let shared = note_book_contract
+        .view("get_shared_with")
+        .args_json(json!({"account_id": manager.id()}))
+        .await?
+        .json::<serde_json::Value>()?;
+
+    assert_eq!(shared[0]["account_id"], user.id().as_str());
+    assert_eq!(shared[0]["permission"], "Write");
+
+    manager
+        .call(note_book_contract.id(), "update_note")
+        .args_json(update)
+        .transact()
+        .await?
+        .into_result()?;
+
+    let note = note_book_contract
+        .view("get_note")
+        .args_json(json!({"account_id": user.id(), "id": U64(0)}))
+        .await?
+        .json::<PostedNoteNew>()?;
+
+    assert_eq!(note.title, "Hello");
+    assert_eq!(&note.author, user.id());
+
+    Ok(())
 }
 
 #[tokio::test]
-async fn add_manager() -> color_eyre::Result<()> {
-    todo!("HOMEWORK");
+async fn workspace() -> color_eyre::Result<()> {
+    let Env {
+        note_book_contract,
+        user,
+        manager,
+        ..
+    } = prepare().await?;
+
+    let id = user
+        .call(note_book_contract.id(), "create_workspace")
+        .deposit(NearToken::from_near(1))
+        .args_json(json!({"name": "team"}))
+        .transact()
+        .await?
+        .into_result()?
+        .json::<U64>()?;
+
+    let res = manager
+        .call(note_book_contract.id(), "add_workspace_note")
+        .args_json(
+            json!({"workspace_id": id, "title": "Hello", "body": "World"}),
+        )
+        .transact()
+        .await?;
+
+    assert_failure(&res, "ERR_NOT_MEMBER");
+
+    user.call(note_book_contract.id(), "invite_member")
+        .args_json(json!({"workspace_id": id, "account_id": manager.id()}))
+        .transact()
+        .await?
+        .into_result()?;
+
+    manager
+        .call(note_book_contract.id(), "accept_invite")
+        .args_json(json!({"workspace_id": id}))
+        .transact()
+        .await?
+        .into_result()?;
+
+    manager
+        .call(note_book_contract.id(), "add_workspace_note")
+        .args_json(
+            json!({"workspace_id": id, "title": "Hello", "body": "World"}),
+        )
+        .transact()
+        .await?
+        .into_result()?;
+
+    let notes = note_book_contract
+        .view("get_workspace_notes")
+        .args_json(json!({"workspace_id": id}))
+        .await?
+        .json::<Vec<PostedNoteNew>>()?;
+
+    assert_eq!(notes.len(), 1);
+    assert_eq!(notes[0].title, "Hello");
+    assert_eq!(&notes[0].author, manager.id());
+
+    Ok(())
 }
 
 #[tokio::test]
-async fn remove_manager() -> color_eyre::Result<()> {
-    todo!("HOMEWORK");
+async fn note_history() -> color_eyre::Result<()> {
+    let Env {
+        note_book_contract,
+        user,
+        ..
+    } = prepare().await?;
+
+    user.call(note_book_contract.id(), "add_note")
+        .deposit(NearToken::from_near(1))
+        .args_json(json!({"title": "Helo", "body": "World"}))
+        .transact()
+        .await?
+        .into_result()?;
+
+    user.call(note_book_contract.id(), "update_note")
+        .args_json(json!({"id": U64(0), "title": "Hello", "body": "World"}))
+        .transact()
+        .await?
+        .into_result()?;
+
+    let history = note_book_contract
+        .view("get_note_history")
+        .args_json(json!({"account_id": user.id(), "id": U64(0)}))
+        .await?
+        .json::<serde_json::Value>()?;
+
+    assert_eq!(history[0]["title"], "Helo");
+    assert_eq!(history[0]["editor"], user.id().as_str());
+
+    user.call(note_book_contract.id(), "restore_note_revision")
+        .args_json(json!({"id": U64(0), "revision": 0}))
+        .transact()
+        .await?
+        .into_result()?;
+
+    let note = note_book_contract
+        .view("get_note")
+        .args_json(json!({"account_id": user.id(), "id": U64(0)}))
+        .await?
+        .json::<PostedNote>()?;
+
+    assert_eq!(note.title, "Helo");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn encrypted_note() -> color_eyre::Result<()> {
+    let Env {
+        note_book_contract,
+        user,
+        manager,
+        ..
+    } = prepare().await?;
+
+    // The contract only checks the lengths of what it's given
+    let envelope = json!({
+        "recipient": manager.id(),
+        "ephemeral_public_key": Base64VecU8::from(vec![1; 32]),
+        "nonce": Base64VecU8::from(vec![2; 12]),
+        "wrapped_key": Base64VecU8::from(vec![3; 48]),
+    });
+
+    let id = user
+        .call(note_book_contract.id(), "add_encrypted_note")
+        .deposit(NearToken::from_near(1))
+        .args_json(json!({
+            "title": "Hello",
+            "encrypted": {
+                "scheme": "X25519ChaCha20Poly1305",
+                "context": Base64VecU8::from(vec![6; 16]),
+                "nonce": Base64VecU8::from(vec![4; 12]),
+                "ciphertext": Base64VecU8::from(vec![5; 32]),
+            },
+            "envelopes": [envelope],
+        }))
+        .transact()
+        .await?
+        .into_result()?
+        .json::<U64>()?;
+
+    let note = note_book_contract
+        .view("get_note")
+        .args_json(json!({"account_id": user.id(), "id": id}))
+        .await?
+        .json::<serde_json::Value>()?;
+
+    assert_eq!(note["body"], "");
+    assert_eq!(note["encrypted"]["scheme"], "X25519ChaCha20Poly1305");
+
+    let key = note_book_contract
+        .view("get_note_key")
+        .args_json(json!({
+            "account_id": user.id(),
+            "id": id,
+            "recipient": manager.id(),
+        }))
+        .await?
+        .json::<serde_json::Value>()?;
+
+    assert_eq!(key, envelope);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn note_ref() -> color_eyre::Result<()> {
+    let Env {
+        note_book_contract,
+        user,
+        ..
+    } = prepare().await?;
+
+    let content_ref = json!({
+        "cid": "bafybeigdyrzt5sfp7udm7hu76uh7y26nf3efuylqabf3oclgtqy55fbzdi",
+        "url": "ar://bNbA3TEQVL60xlgCcqdz4ZPHFZ711cZ3hmkpGttDt_U",
+        "sha256": Base58CryptoHash::from([7; 32]),
+    });
+
+    let id = user
+        .call(note_book_contract.id(), "add_note_ref")
+        .deposit(NearToken::from_near(1))
+        .args_json(json!({"title": "Hello", "content_ref": content_ref}))
+        .transact()
+        .await?
+        .into_result()?
+        .json::<U64>()?;
+
+    let note = note_book_contract
+        .view("get_note")
+        .args_json(json!({"account_id": user.id(), "id": id}))
+        .await?
+        .json::<serde_json::Value>()?;
+
+    assert_eq!(note["body"], "");
+    assert_eq!(note["content_ref"], content_ref);
+
+    // A reference has to say where the content can be fetched from
+    let res = user
+        .call(note_book_contract.id(), "add_note_ref")
+        .deposit(NearToken::from_near(1))
+        .args_json(json!({
+            "title": "Hello",
+            "content_ref": {"sha256": Base58CryptoHash::from([7; 32])},
+        }))
+        .transact()
+        .await?;
+
+    assert_failure(&res, "ERR_INVALID_CONTENT_REF");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn attachments() -> color_eyre::Result<()> {
+    let Env {
+        note_book_contract,
+        user,
+        ..
+    } = prepare().await?;
+
+    user.call(note_book_contract.id(), "add_note")
+        .deposit(NearToken::from_near(1))
+        .args_json(json!({"title": "Hello", "body": "World"}))
+        .transact()
+        .await?
+        .into_result()?;
+
+    let attachment = json!({
+        "name": "screenshot.png",
+        "mime_type": "image/png",
+        "size": U64(1024),
+        "sha256": Base58CryptoHash::from([7; 32]),
+        "uri": "ipfs://bafybeigdyrzt5sfp7udm7hu76uh7y26nf3efuylqabf3oclgtqy55f",
+    });
+
+    user.call(note_book_contract.id(), "add_attachment")
+        .args_json(json!({"id": U64(0), "attachment": attachment}))
+        .transact()
+        .await?
+        .into_result()?;
+
+    let attachments = note_book_contract
+        .view("get_attachments")
+        .args_json(json!({"account_id": user.id(), "id": U64(0)}))
+        .await?
+        .json::<serde_json::Value>()?;
+
+    assert_eq!(attachments, json!([attachment]));
+
+    let res = user
+        .call(note_book_contract.id(), "add_attachment")
+        .args_json(json!({"id": U64(0), "attachment": attachment}))
+        .transact()
+        .await?;
+
+    assert_failure(&res, "ERR_DUPLICATE_ATTACHMENT");
+
+    user.call(note_book_contract.id(), "remove_attachment")
+        .args_json(json!({"id": U64(0), "name": "screenshot.png"}))
+        .transact()
+        .await?
+        .into_result()?;
+
+    let attachments = note_book_contract
+        .view("get_attachments")
+        .args_json(json!({"account_id": user.id(), "id": U64(0)}))
+        .await?
+        .json::<serde_json::Value>()?;
+
+    assert_eq!(attachments, json!([]));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn note_limits() -> color_eyre::Result<()> {
+    let Env {
+        note_book_contract,
+        user,
+        ..
+    } = prepare().await?;
+
+    let limits = note_book_contract
+        .view("get_note_limits")
+        .await?
+        .json::<serde_json::Value>()?;
+
+    let max_body_length = limits["max_body_length"].as_u64().unwrap();
+
+    for (title, body, code) in [
+        ("", "World".to_string(), "ERR_EMPTY_TITLE"),
+        ("Hello", "World\u{0}".to_string(), "ERR_INVALID_BODY"),
+        (
+            "Hello",
+            "a".repeat(max_body_length as usize + 1),
+            "ERR_BODY_TOO_LONG",
+        ),
+    ] {
+        let res = user
+            .call(note_book_contract.id(), "add_note")
+            .deposit(NearToken::from_near(1))
+            .args_json(json!({"title": title, "body": body}))
+            .max_gas()
+            .transact()
+            .await?;
+
+        assert_failure(&res, code);
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn ft_invalid_message() -> color_eyre::Result<()> {
+    let Env {
+        note_book_contract,
+        ft_contract,
+        manager,
+        ..
+    } = prepare().await?;
+
+    let res = manager
+        .call(ft_contract.id(), "ft_transfer_call")
+        .args_json(json!({
+            "receiver_id": note_book_contract.id(),
+            "amount": U128(NearToken::from_near(1).as_yoctonear()),
+            "msg": "AddNote",
+        }))
+        .max_gas()
+        .deposit(NearToken::from_yoctonear(1))
+        .transact()
+        .await?;
+
+    // The transfer itself is refunded, only `ft_on_transfer` fails
+    assert!(format!("{:?}", res.failures()).contains("ERR_INVALID_MESSAGE"));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn add_note_ft() -> color_eyre::Result<()> {
+    let Env {
+        note_book_contract,
+        ft_contract,
+        user,
+        manager,
+        ..
+    } = prepare().await?;
+
+    manager
+        .call(ft_contract.id(), "ft_transfer")
+        .args_json(json!({
+            "receiver_id": user.id(),
+            "amount": U128(NearToken::from_near(2).as_yoctonear()),
+        }))
+        .deposit(NearToken::from_yoctonear(1))
+        .transact()
+        .await?
+        .into_result()?;
+
+    // Notes added with tokens draw from the storage balance
+    user.call(note_book_contract.id(), "storage_deposit")
+        .args_json(json!({}))
+        .deposit(NearToken::from_millinear(100))
+        .transact()
+        .await?
+        .into_result()?;
+
+    let res = user
+        .call(ft_contract.id(), "ft_transfer_call")
+        .args_json(json!({
+            "receiver_id": note_book_contract.id(),
+            "amount": U128(NearToken::from_near(1).as_yoctonear()),
+            "msg": serde_json::to_string(&FtMessage::AddNote(PostedNote {
+                id: None,
+                title: "Hello".to_string(),
+                body: "World".to_string(),
+            }))?,
+        }))
+        .max_gas()
+        .deposit(NearToken::from_yoctonear(1))
+        .transact()
+        .await?
+        .into_result()?;
+
+    assert!(res
+        .logs()
+        .iter()
+        .find(|log| log.contains(r#""event":"note_added""#))
+        .is_some());
+
+    let notes = note_book_contract
+        .view("get_notes")
+        .args_json(json!({"account_id": user.id()}))
+        .await?
+        .json::<Vec<PostedNote>>()?;
+
+    assert_eq!(notes.len(), 1);
+    assert_eq!(notes[0].title, "Hello");
+    assert_eq!(notes[0].body, "World");
+
+    let points = note_book_contract
+        .view("get_user_points")
+        .args_json(json!({"account_id": user.id()}))
+        .await?
+        .json::<U128>()?;
+
+    assert_eq!(points.0, NearToken::from_near(1).as_yoctonear());
+
+    let storage_balance = note_book_contract
+        .view("storage_balance_of")
+        .args_json(json!({"account_id": user.id()}))
+        .await?
+        .json::<serde_json::Value>()?;
+
+    assert_eq!(
+        storage_balance["total"],
+        NearToken::from_millinear(100).as_yoctonear().to_string()
+    );
+    assert_ne!(storage_balance["available"], storage_balance["total"]);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn remove_note() -> color_eyre::Result<()> {
+    let Env {
+        note_book_contract,
+        user,
+        ..
+    } = prepare().await?;
+
+    user.call(note_book_contract.id(), "add_note")
+        .deposit(NearToken::from_near(1))
+        .args_json(json!({"title": "Hello", "body": "World"}))
+        .transact()
+        .await?
+        .into_result()?;
+
+    let res = user
+        .call(note_book_contract.id(), "remove_note")
+        .args_json(json!({"id": U64(0)}))
+        .transact()
+        .await?
+        .into_result()?;
+
+    assert!(res
+        .logs()
+        .iter()
+        .any(|log| log.contains(r#""event":"note_removed""#)));
+
+    let res = note_book_contract
+        .view("get_note")
+        .args_json(json!({"account_id": user.id(), "id": U64(0)}))
+        .await;
+
+    assert!(res.is_err());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn remove_note_ft() -> color_eyre::Result<()> {
+    let Env {
+        note_book_contract,
+        ft_contract,
+        user,
+        manager,
+        ..
+    } = prepare().await?;
+
+    manager
+        .call(ft_contract.id(), "ft_transfer")
+        .args_json(json!({
+            "receiver_id": user.id(),
+            "amount": U128(NearToken::from_near(2).as_yoctonear()),
+        }))
+        .deposit(NearToken::from_yoctonear(1))
+        .transact()
+        .await?
+        .into_result()?;
+
+    user.call(note_book_contract.id(), "storage_deposit")
+        .args_json(json!({}))
+        .deposit(NearToken::from_millinear(100))
+        .transact()
+        .await?
+        .into_result()?;
+
+    user.call(ft_contract.id(), "ft_transfer_call")
+        .args_json(json!({
+            "receiver_id": note_book_contract.id(),
+            "amount": U128(NearToken::from_near(1).as_yoctonear()),
+            "msg": serde_json::to_string(&FtMessage::AddNote(PostedNote {
+                id: None,
+                title: "Hello".to_string(),
+                body: "World".to_string(),
+            }))?,
+        }))
+        .max_gas()
+        .deposit(NearToken::from_yoctonear(1))
+        .transact()
+        .await?
+        .into_result()?;
+
+    let res = user
+        .call(ft_contract.id(), "ft_transfer_call")
+        .args_json(json!({
+            "receiver_id": note_book_contract.id(),
+            "amount": U128(1),
+            "msg": serde_json::to_string(&FtMessage::RemoveNote(U64(0)))?,
+        }))
+        .max_gas()
+        .deposit(NearToken::from_yoctonear(1))
+        .transact()
+        .await?
+        .into_result()?;
+
+    assert!(res
+        .logs()
+        .iter()
+        .any(|log| log.contains("Note deposit refunded")));
+
+    let res = note_book_contract
+        .view("get_note")
+        .args_json(json!({"account_id": user.id(), "id": U64(0)}))
+        .await;
+
+    assert!(res.is_err());
+
+    let points = note_book_contract
+        .view("get_user_points")
+        .args_json(json!({"account_id": user.id()}))
+        .await?
+        .json::<U128>()?;
+
+    assert_eq!(points.0, 0);
+
+    let balance = ft_contract
+        .view("ft_balance_of")
+        .args_json(json!({"account_id": user.id()}))
+        .await?
+        .json::<U128>()?;
+
+    assert_eq!(balance.0, NearToken::from_near(2).as_yoctonear());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn pause() -> color_eyre::Result<()> {
+    let Env {
+        note_book_contract,
+        user,
+        manager,
+        ..
+    } = prepare().await?;
+
+    let res = user
+        .call(note_book_contract.id(), "pause")
+        .transact()
+        .await?;
+
+    assert_failure(&res, "ERR_MISSING_ROLE");
+
+    manager
+        .call(note_book_contract.id(), "pause")
+        .transact()
+        .await?
+        .into_result()?;
+
+    let paused = note_book_contract
+        .view("is_paused")
+        .args_json(json!({}))
+        .await?
+        .json::<bool>()?;
+
+    assert!(paused);
+
+    let res = user
+        .call(note_book_contract.id(), "add_note")
+        .deposit(NearToken::from_near(1))
+        .args_json(json!({"title": "Hello", "body": "World"}))
+        .transact()
+        .await?;
+
+    assert_failure(&res, "ERR_PAUSED");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn grant_role() -> color_eyre::Result<()> {
+    let Env {
+        note_book_contract,
+        manager,
+        user,
+        ..
+    } = prepare().await?;
+
+    let res = execute_action(
+        &manager,
+        &note_book_contract,
+        json!({"GrantRole": {"account_id": user.id(), "role": "Pauser"}}),
+    )
+    .await?
+    .into_result()?;
+
+    assert!(res
+        .logs()
+        .iter()
+        .any(|log| log.contains(r#""event":"role_granted""#)));
+
+    let pausers = note_book_contract
+        .view("get_role_members")
+        .args_json(json!({"role": "Pauser"}))
+        .await?
+        .json::<Vec<AccountId>>()?;
+
+    assert_eq!(pausers, vec![user.id().clone()]);
+
+    // the pauser can pause but not upgrade the contract
+    user.call(note_book_contract.id(), "pause")
+        .transact()
+        .await?
+        .into_result()?;
+
+    let res = user
+        .call(note_book_contract.id(), "stage_code")
+        .args(NOTE_BOOK_CONTRACT.to_vec())
+        .deposit(NearToken::from_near(5))
+        .max_gas()
+        .transact()
+        .await?;
+
+    assert_failure(&res, "ERR_MISSING_ROLE");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn revoke_role() -> color_eyre::Result<()> {
+    let Env {
+        note_book_contract,
+        manager,
+        user,
+        ..
+    } = prepare().await?;
+
+    // the last admin can't be revoked
+    let res = execute_action(
+        &manager,
+        &note_book_contract,
+        json!({"RevokeRole": {"account_id": manager.id(), "role": "Admin"}}),
+    )
+    .await?;
+
+    assert_failure(&res, "ERR_LAST_ADMIN");
+
+    execute_action(
+        &manager,
+        &note_book_contract,
+        json!({"GrantRole": {"account_id": user.id(), "role": "Pauser"}}),
+    )
+    .await?
+    .into_result()?;
+
+    let res = execute_action(
+        &manager,
+        &note_book_contract,
+        json!({"RevokeRole": {"account_id": user.id(), "role": "Pauser"}}),
+    )
+    .await?
+    .into_result()?;
+
+    assert!(res
+        .logs()
+        .iter()
+        .any(|log| log.contains(r#""event":"role_revoked""#)));
+
+    let has_role = note_book_contract
+        .view("has_role")
+        .args_json(json!({"account_id": user.id(), "role": "Pauser"}))
+        .await?
+        .json::<bool>()?;
+
+    assert!(!has_role);
+
+    // the account lost its rights
+    let res = user
+        .call(note_book_contract.id(), "pause")
+        .transact()
+        .await?;
+
+    assert_failure(&res, "ERR_MISSING_ROLE");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn deploy_staged() -> color_eyre::Result<()> {
+    let Env {
+        note_book_contract,
+        manager,
+        user,
+        ..
+    } = prepare().await?;
+
+    execute_action(
+        &manager,
+        &note_book_contract,
+        json!({"SetUpgradeThreshold": {"threshold": 2}}),
+    )
+    .await?
+    .into_result()?;
+
+    execute_action(
+        &manager,
+        &note_book_contract,
+        json!({"GrantRole": {"account_id": user.id(), "role": "Upgrader"}}),
+    )
+    .await?
+    .into_result()?;
+
+    let hash = manager
+        .call(note_book_contract.id(), "stage_code")
+        .args(NOTE_BOOK_CONTRACT.to_vec())
+        .deposit(NearToken::from_near(5))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?
+        .json::<String>()?;
+
+    // a single approval isn't enough
+    let res = manager
+        .call(note_book_contract.id(), "deploy_staged")
+        .args_json(json!({"hash": hash}))
+        .max_gas()
+        .transact()
+        .await?;
+
+    assert_failure(&res, "ERR_NOT_ENOUGH_APPROVALS");
+
+    user.call(note_book_contract.id(), "approve_code")
+        .args_json(json!({"hash": hash}))
+        .transact()
+        .await?
+        .into_result()?;
+
+    let proposal = note_book_contract
+        .view("get_upgrade_proposal")
+        .args_json(json!({"hash": hash}))
+        .await?
+        .json::<serde_json::Value>()?;
+
+    assert_eq!(proposal["approvals"], json!([manager.id(), user.id()]));
+
+    let staged_code = note_book_contract
+        .view("get_staged_code")
+        .args_json(json!({"hash": hash}))
+        .await?
+        .json::<serde_json::Value>()?;
+
+    assert_eq!(
+        staged_code["length"],
+        json!(NOTE_BOOK_CONTRACT.len().to_string())
+    );
+
+    // the code is read back in chunks
+    let mut code: Vec<u8> = vec![];
+
+    loop {
+        let chunk = note_book_contract
+            .view("get_staged_code_chunk")
+            .args_json(
+                json!({"hash": hash, "from_index": code.len().to_string()}),
+            )
+            .await?
+            .json::<near_sdk::json_types::Base64VecU8>()?;
+
+        if chunk.0.is_empty() {
+            break;
+        }
+
+        code.extend(chunk.0);
+    }
+
+    assert_eq!(code, NOTE_BOOK_CONTRACT);
+
+    user.call(note_book_contract.id(), "deploy_staged")
+        .args_json(json!({"hash": hash}))
+        .max_gas()
+        .transact()
+        .await?
+        .into_result()?;
+
+    let history = note_book_contract
+        .view("get_upgrade_history")
+        .args_json(json!({}))
+        .await?
+        .json::<Vec<serde_json::Value>>()?;
+
+    assert_eq!(history.len(), 1);
+    assert_eq!(history[0]["hash"], json!(hash));
+    assert_eq!(history[0]["success"], json!(true));
+
+    // the deployed code is no longer staged
+    let proposal = note_book_contract
+        .view("get_upgrade_proposal")
+        .args_json(json!({"hash": hash}))
+        .await?
+        .json::<Option<serde_json::Value>>()?;
+
+    assert!(proposal.is_none());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn timelock() -> color_eyre::Result<()> {
+    let Env {
+        note_book_contract,
+        manager,
+        user,
+        ..
+    } = prepare().await?;
+
+    execute_action(
+        &manager,
+        &note_book_contract,
+        json!({"SetTimelockDelay": {"delay": "86400000000000"}}),
+    )
+    .await?
+    .into_result()?;
+
+    let id = manager
+        .call(note_book_contract.id(), "queue_action")
+        .args_json(json!({
+            "action": {"GrantRole": {"account_id": user.id(), "role": "Admin"}}
+        }))
+        .transact()
+        .await?
+        .into_result()?
+        .json::<U64>()?;
+
+    // the action is visible before it lands
+    let queued_actions = note_book_contract
+        .view("get_queued_actions")
+        .args_json(json!({}))
+        .await?
+        .json::<Vec<serde_json::Value>>()?;
+
+    assert_eq!(queued_actions.len(), 1);
+    assert_eq!(queued_actions[0]["proposer"], json!(manager.id()));
+
+    let res = manager
+        .call(note_book_contract.id(), "execute_action")
+        .args_json(json!({"id": id}))
+        .transact()
+        .await?;
+
+    assert_failure(&res, "ERR_TIMELOCKED");
+
+    manager
+        .call(note_book_contract.id(), "cancel_action")
+        .args_json(json!({"id": id}))
+        .transact()
+        .await?
+        .into_result()?;
+
+    let queued_actions = note_book_contract
+        .view("get_queued_actions")
+        .args_json(json!({}))
+        .await?
+        .json::<Vec<serde_json::Value>>()?;
+
+    assert!(queued_actions.is_empty());
+
+    Ok(())
 }
 
 #[tokio::test]
@@ -375,7 +1569,7 @@ async fn exchange_ft_wnear() -> color_eyre::Result<()> {
     assert!(res
         .logs()
         .iter()
-        .find(|log| log.contains("Exchange successful"))
+        .find(|log| is_exchange_successful(log))
         .is_some());
 
     let points = note_book_contract
@@ -396,11 +1590,7 @@ async fn exchange_ft_wnear() -> color_eyre::Result<()> {
         .receipt_outcomes()
         .into_iter()
         .find_map(|receipt| {
-            if receipt
-                .logs
-                .iter()
-                .any(|log| log.contains("Exchange successful"))
-            {
+            if receipt.logs.iter().any(|log| is_exchange_successful(log)) {
                 let val = receipt.clone().into_result().unwrap();
 
                 if let ValueOrReceiptId::Value(amount) = val {